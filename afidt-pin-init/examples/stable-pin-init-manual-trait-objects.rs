@@ -2,238 +2,514 @@
 //! stable Rust by avoiding Pointer Metadata APIs and reinventing trait objects.
 //!
 //! Original athuor is [@loichyan](https://github.com/loichyan).
+//!
+//! The simulated `dyn` objects, i.e. [`DynCompatible`], [`DynInit`] and
+//! [`DynBox`], live in the `afidt` crate, on top of which this file builds
+//! objects for arbitrary traits.
 #![allow(unsafe_op_in_unsafe_fn)]
 #![allow(clippy::disallowed_names)]
 #![allow(clippy::missing_safety_doc)]
 
 use std::alloc::Layout;
+use std::fmt;
 use std::future::Future;
-use std::marker::PhantomPinned;
-use std::mem::ManuallyDrop;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use std::pin::Pin;
 use std::ptr::NonNull;
 use std::task::{Context, Poll};
 
-// =========== 获取函数返回类型 ===========
-// 参考 <https://docs.rs/axum/latest/axum/handler/trait.Handler.html>
-mod function {
-    use std::any::Any;
-    use std::convert::Infallible;
+use afidt::*;
 
-    use super::*;
+// =========== 为任意 trait 生成 dyn object ===========
+mod dyn_trait {
+    pub use super::*;
 
-    pub trait Function<Input> {
-        type Output;
-    }
+    /// Defines an object safe trait along with its simulated `dyn` object,
+    /// just like [`DynFuture`] for [`Future`]:
+    ///
+    /// - `dyn Trait<..>` implements [`DynCompatible`], whose object is
+    ///   `DynTrait<dyn Trait<..>>` that implements the trait itself.
+    /// - All implementors of the trait implement [`CoerceDyn`].
+    /// - The vtable lives in the given module.
+    ///
+    /// Methods take `&self`, `&mut self` or `self: Pin<&mut Self>`, and must not
+    /// have generic parameters.
+    ///
+    /// ```ignore
+    /// dyn_trait! {
+    ///     pub trait Iter: dyn DynIter, mod iter_vtable {
+    ///         type Item;
+    ///         fn next(&mut self) -> Option<Self::Item>;
+    ///     }
+    /// }
+    /// ```
+    macro_rules! dyn_trait {
+        (
+            $(#[$attr:meta])*
+            $vis:vis trait $Trait:ident: dyn $Dyn:ident, mod $vtable:ident {
+                $(type $Assoc:ident;)*
+                $(#[$mattr:meta])*
+                fn $($methods:tt)*
+            }
+        ) => {
+            dyn_trait!(@parse
+                [$(#[$attr])* $vis $Trait $Dyn $vtable [$($Assoc)*]]
+                []
+                $(#[$mattr])*
+                fn $($methods)*
+            );
+        };
+
+        // normalize methods into `{ attrs name [receiver] kind [args] [-> ret] }`
+        (@parse $header:tt [$($parsed:tt)*]
+            $(#[$mattr:meta])*
+            fn $name:ident(&mut $self:ident $(, $arg:ident: $ty:ty)* $(,)?) $(-> $ret:ty)?;
+            $($rest:tt)*
+        ) => {
+            dyn_trait!(@parse $header [$($parsed)*
+                { [$(#[$mattr])*] $name $self [&mut $self] as_mut [$($arg: $ty),*] [$(-> $ret)?] }
+            ] $($rest)*);
+        };
+        (@parse $header:tt [$($parsed:tt)*]
+            $(#[$mattr:meta])*
+            fn $name:ident(&$self:ident $(, $arg:ident: $ty:ty)* $(,)?) $(-> $ret:ty)?;
+            $($rest:tt)*
+        ) => {
+            dyn_trait!(@parse $header [$($parsed)*
+                { [$(#[$mattr])*] $name $self [&$self] as_ref [$($arg: $ty),*] [$(-> $ret)?] }
+            ] $($rest)*);
+        };
+        (@parse $header:tt [$($parsed:tt)*]
+            $(#[$mattr:meta])*
+            fn $name:ident($self:ident: Pin<&mut Self> $(, $arg:ident: $ty:ty)* $(,)?) $(-> $ret:ty)?;
+            $($rest:tt)*
+        ) => {
+            dyn_trait!(@parse $header [$($parsed)*
+                { [$(#[$mattr])*] $name $self [$self: ::std::pin::Pin<&mut Self>] as_pin [$($arg: $ty),*] [$(-> $ret)?] }
+            ] $($rest)*);
+        };
+
+        (@parse
+            [$(#[$attr:meta])* $vis:vis $Trait:ident $Dyn:ident $vtable:ident [$($Assoc:ident)*]]
+            [$({
+                [$(#[$mattr:meta])*] $name:ident $self:ident [$($recv:tt)*] $kind:ident
+                [$($arg:ident: $ty:ty),*] [$($ret:tt)*]
+            })*]
+        ) => {
+            $(#[$attr])*
+            $vis trait $Trait {
+                $(type $Assoc;)*
+                $($(#[$mattr])* fn $name($($recv)*, $($arg: $ty),*) $($ret)*;)*
+            }
 
-    macro_rules! impl_function {
-    ($($i:ident),* -> $o:ident) => {
-        impl<Fn, $($i,)* $o> Function<($($i,)*)> for Fn
-        where
-            Fn: FnOnce($($i,)*) -> $o,
-        {
-            type Output = $o;
+            $vis mod $vtable {
+                use super::*;
+
+                /// The function pointer types of the methods.
+                #[allow(non_camel_case_types)]
+                pub trait Signatures: $Trait {
+                    $(type $name;)*
+                }
+                impl<Dyn: ?Sized + $Trait> Signatures for Dyn {
+                    $(type $name = unsafe fn(::afidt::VoidPtr, $($ty),*) $($ret)*;)*
+                }
+
+                pub struct Vtable<Dyn: ?Sized + $Trait> {
+                    pub layout: fn() -> ::std::alloc::Layout,
+                    pub drop_fn: unsafe fn(::afidt::VoidPtr),
+                    $(pub $name: <Dyn as Signatures>::$name,)*
+                    pub _marker: ::std::marker::PhantomData<*const Dyn>,
+                }
+            }
+
+            $vis struct $Dyn<Dyn: ?Sized + $Trait> {
+                data: ::afidt::VoidPtr,
+                vtable: *const $vtable::Vtable<Dyn>,
+                _pinned: ::std::marker::PhantomPinned,
+            }
+
+            impl<Dyn: ?Sized + $Trait> $Trait for $Dyn<Dyn> {
+                $(type $Assoc = Dyn::$Assoc;)*
+                $(
+                    fn $name($($recv)*, $($arg: $ty),*) $($ret)* {
+                        unsafe { ((*$self.vtable).$name)($self.data, $($arg),*) }
+                    }
+                )*
+            }
+
+            impl<Dyn: ?Sized + $Trait> Drop for $Dyn<Dyn> {
+                fn drop(&mut self) {
+                    unsafe { ((*self.vtable).drop_fn)(self.data) }
+                }
+            }
+
+            unsafe impl<$($Assoc),*> ::afidt::DynCompatible for dyn $Trait<$($Assoc = $Assoc),*> {
+                type Object = $Dyn<Self>;
+
+                fn data(this: &Self::Object) -> ::afidt::VoidPtr {
+                    this.data
+                }
+                fn layout(this: &Self::Object) -> ::std::alloc::Layout {
+                    unsafe { ((*this.vtable).layout)() }
+                }
+            }
+
+            unsafe impl<T: $Trait> ::afidt::CoerceFrom<T> for dyn $Trait<$($Assoc = T::$Assoc),*> {
+                unsafe fn construct(data: ::afidt::VoidPtr) -> $Dyn<Self> {
+                    let vtable = const {
+                        &$vtable::Vtable::<dyn $Trait<$($Assoc = T::$Assoc),*>> {
+                            layout: ::std::alloc::Layout::new::<T>,
+                            drop_fn: |data| unsafe { data.cast::<T>().drop_in_place() },
+                            $($name: |data, $($arg: $ty),*| unsafe {
+                                T::$name(dyn_trait!(@this $kind data T), $($arg),*)
+                            },)*
+                            _marker: ::std::marker::PhantomData,
+                        }
+                    };
+                    $Dyn {
+                        data,
+                        vtable,
+                        _pinned: ::std::marker::PhantomPinned,
+                    }
+                }
+            }
+        };
+
+        (@this as_ref $data:ident $T:ident) => { $data.cast::<$T>().as_ref() };
+        (@this as_mut $data:ident $T:ident) => { $data.cast::<$T>().as_mut() };
+        (@this as_pin $data:ident $T:ident) => {
+            ::std::pin::Pin::new_unchecked($data.cast::<$T>().as_mut())
+        };
+    }
+    pub(crate) use dyn_trait;
+
+    dyn_trait! {
+        /// An object safe iterator.
+        pub trait Iter: dyn DynIter, mod iter_vtable {
+            type Item;
+            fn next(&mut self) -> Option<Self::Item>;
+            fn size_hint(&self) -> (usize, Option<usize>);
         }
-    };
-}
-    impl_function!(A                -> R);
-    impl_function!(A, B             -> R);
-    impl_function!(A, B, C          -> R);
-    impl_function!(A, B, C, D       -> R);
-    impl_function!(A, B, C, D, E    -> R);
-    impl_function!(A, B, C, D, E, F -> R);
-
-    pub const fn return_type_dangling_ptr<I, F: Function<I>>(_: &F) -> *mut F::Output {
-        std::ptr::dangling_mut()
     }
 
-    pub const fn return_type_layout<I, F: Function<I>>(_: &F) -> Layout {
-        Layout::new::<F::Output>()
+    dyn_trait! {
+        pub trait Codec: dyn DynCodec, mod codec_vtable {
+            fn encode(&self, input: &str, out: &mut Vec<u8>);
+        }
     }
 
-    pub const fn return_type_cast_ptr<I, F: Function<I>>(
-        _: &F,
-        ptr: VoidPtr,
-    ) -> NonNull<F::Output> {
-        ptr.cast()
+    dyn_trait! {
+        pub trait Stream: dyn DynStream, mod stream_vtable {
+            type Item;
+            fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+        }
     }
 
-    pub fn test_return_type_layout() {
-        fn f1(_: usize, _: usize) -> usize {
-            todo!()
-        }
-        fn f2(_: &str) -> &str {
-            todo!()
+    pub async fn test_dyn_trait() {
+        struct Range(u32, u32);
+        impl Iter for Range {
+            type Item = u32;
+            fn next(&mut self) -> Option<u32> {
+                (self.0 < self.1).then(|| {
+                    self.0 += 1;
+                    self.0 - 1
+                })
+            }
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                let len = (self.1 - self.0) as usize;
+                (len, Some(len))
+            }
         }
-        fn f3(_: String, _: Vec<u8>, _: &dyn Any) -> Box<dyn Any> {
-            todo!()
+        let init = unsafe {
+            DynInit::<dyn Iter<Item = u32>, _>::new((0, 3), Layout::new::<Range>, |slot, (a, b)| {
+                slot.cast().write(Range(a, b));
+                <Range as CoerceDyn<dyn Iter<Item = u32>>>::construct(slot)
+            })
+        };
+        let mut stack = MaybeUninit::<[u32; 4]>::uninit();
+        assert!(init.layout().size() <= size_of_val(&stack));
+        let mut iter = unsafe { init.init(NonNull::from(&mut stack).cast()) };
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        assert_eq!([iter.next(), iter.next()], [Some(0), Some(1)]);
+        assert_eq!(iter.size_hint(), (1, Some(1)));
+
+        struct Hex(Vec<String>);
+        impl Codec for Hex {
+            fn encode(&self, input: &str, out: &mut Vec<u8>) {
+                for b in input.bytes() {
+                    out.extend(format!("{b:02x}").bytes());
+                }
+                out.extend(self.0.concat().bytes());
+            }
         }
-        fn f4(_: usize, _: usize) -> Infallible {
-            todo!()
+        let init = unsafe {
+            DynInit::<dyn Codec, _>::new(vec!["!".to_owned()], Layout::new::<Hex>, |slot, s| {
+                slot.cast().write(Hex(s));
+                <Hex as CoerceDyn<dyn Codec>>::construct(slot)
+            })
+        };
+        // the `Vec` in the codec is dropped along with the box
+        let codec = DynBox::init(init);
+        let mut out = Vec::new();
+        codec.encode("ab", &mut out);
+        assert_eq!(out, b"6162!");
+
+        struct Countup(u32);
+        impl Stream for Countup {
+            type Item = u32;
+            fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<u32>> {
+                self.0 += 1;
+                Poll::Ready((self.0 <= 2).then_some(self.0))
+            }
         }
-
-        assert_eq!(Layout::new::<usize>(), return_type_layout(&f1));
-        assert_eq!(Layout::new::<&str>(), return_type_layout(&f2));
-        assert_eq!(Layout::new::<Box<dyn Any>>(), return_type_layout(&f3));
-        assert_eq!(Layout::new::<Infallible>(), return_type_layout(&f4));
-        println!("test_return_type_layout pass");
+        let init = unsafe {
+            DynInit::<dyn Stream<Item = u32>, _>::new(0, Layout::new::<Countup>, |slot, n| {
+                slot.cast().write(Countup(n));
+                <Countup as CoerceDyn<dyn Stream<Item = u32>>>::construct(slot)
+            })
+        };
+        let mut stream = DynBox::init(init);
+        let items = std::future::poll_fn(|cx| {
+            let mut items = Vec::new();
+            while let Poll::Ready(Some(item)) =
+                unsafe { Pin::new_unchecked(&mut *stream) }.poll_next(cx)
+            {
+                items.push(item);
+            }
+            Poll::Ready(items)
+        })
+        .await;
+        assert_eq!(items, [1, 2]);
+        println!("test_dyn_trait pass");
     }
 }
-pub use function::*;
+pub use dyn_trait::*;
 
-// =========== 模拟 dyn object ===========
-mod dyn_object {
+// =========== 组合多个 vtable ===========
+mod dyn_multi {
+    use std::marker::PhantomData;
 
-    use super::*;
+    pub use super::*;
 
-    pub unsafe trait DynCompatible {
-        type Object;
+    /// A `dyn A + B` that isn't limited to auto traits. Its object consists of
+    /// the objects of `A` and `B`, which point to the same data.
+    ///
+    /// More traits are composed by nesting, e.g. `Both<A, Both<B, C>>`.
+    pub struct Both<A: ?Sized, B: ?Sized>(PhantomData<A>, PhantomData<B>);
 
-        unsafe fn construct(data: VoidPtr) -> Self::Object
-        where
-            Self: Sized;
-        fn data(this: &Self::Object) -> VoidPtr;
-        fn layout(this: &Self::Object) -> Layout;
+    pub struct DynBoth<A: ?Sized + DynCompatible, B: ?Sized + DynCompatible> {
+        a: A::Object,
+        // 只有 `a` 负责析构
+        b: ManuallyDrop<B::Object>,
     }
 
-    pub type VoidPtr = NonNull<Void>;
-    pub enum Void {}
-
-    pub struct DynFuture<Fut: ?Sized + Future> {
-        data: VoidPtr,
-        vtable: *const FutureVtable<Fut>,
-        #[allow(dead_code)]
-        unpin: PhantomPinned,
-    }
-    struct FutureVtable<Fut: ?Sized + Future> {
-        layout: fn() -> Layout,
-        poll_fn: unsafe fn(VoidPtr, cx: &mut Context) -> Poll<Fut::Output>,
-        drop_fn: unsafe fn(VoidPtr),
-    }
-    unsafe impl<Fut> DynCompatible for Fut
+    unsafe impl<A, B> DynCompatible for Both<A, B>
     where
-        Fut: ?Sized + Future,
+        A: ?Sized + DynCompatible,
+        B: ?Sized + DynCompatible,
     {
-        type Object = DynFuture<dyn Future<Output = Fut::Output>>;
+        type Object = DynBoth<A, B>;
 
-        unsafe fn construct(data: VoidPtr) -> Self::Object
-        where
-            Self: Sized,
-        {
-            // 参考 <https://github.com/dtolnay/anyhow/blob/69295727cefb015a184f9b780fcc51ef905a798c/src/error.rs#L155>
-            unsafe fn poll_fn<Fut: Future>(data: VoidPtr, cx: &mut Context) -> Poll<Fut::Output> {
-                Pin::new_unchecked(data.cast::<Fut>().as_mut()).poll(cx)
-            }
-            unsafe fn drop_fn<Fut: Future>(data: VoidPtr) {
-                data.cast::<Fut>().drop_in_place();
-            }
-            fn vtable<Fut: Future>() -> *const FutureVtable<dyn Future<Output = Fut::Output>> {
-                &FutureVtable {
-                    layout: Layout::new::<Fut>,
-                    poll_fn: poll_fn::<Fut>,
-                    drop_fn: drop_fn::<Fut>,
-                }
-            }
-            DynFuture {
-                data: data.cast(),
-                vtable: vtable::<Self>(),
-                unpin: PhantomPinned,
-            }
-        }
         fn data(this: &Self::Object) -> VoidPtr {
-            this.data
+            A::data(&this.a)
         }
         fn layout(this: &Self::Object) -> Layout {
-            unsafe { ((*this.vtable).layout)() }
+            A::layout(&this.a)
         }
     }
 
-    impl<Fut: ?Sized + Future> Future for DynFuture<Fut> {
-        type Output = Fut::Output;
-        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            unsafe {
-                let this = self.get_unchecked_mut();
-                ((*this.vtable).poll_fn)(this.data, cx)
+    unsafe impl<T, A, B> CoerceFrom<T> for Both<A, B>
+    where
+        A: ?Sized + CoerceFrom<T>,
+        B: ?Sized + CoerceFrom<T>,
+    {
+        unsafe fn construct(data: VoidPtr) -> DynBoth<A, B> {
+            DynBoth {
+                a: A::construct(data),
+                b: ManuallyDrop::new(B::construct(data)),
             }
         }
     }
-    impl<Fut: ?Sized + Future> Drop for DynFuture<Fut> {
-        fn drop(&mut self) {
-            // 只调用析构函数，data 本身占用的内存不回收
-            unsafe { ((*self.vtable).drop_fn)(self.data) }
-        }
-    }
-}
-pub use dyn_object::*;
 
-// =========== 封装 DynObject ===========
-mod dyn_init {
-    use std::ops::{Deref, DerefMut};
+    /// Selects the object of `D` from the object of `Self` by the index `I`,
+    /// which is inferred if `D` appears only once.
+    pub trait Select<D: ?Sized + DynCompatible, I>: DynCompatible {
+        fn select(this: &Self::Object) -> &D::Object;
+        fn select_pin(this: Pin<&mut Self::Object>) -> Pin<&mut D::Object>;
+    }
 
-    pub use super::*;
+    pub struct Here;
+    pub struct First<I>(PhantomData<I>);
+    pub struct Second<I>(PhantomData<I>);
 
-    pub struct DynInit<Dyn: ?Sized + DynCompatible, Args> {
-        args: Args,
-        layout: fn() -> Layout,
-        init: fn(VoidPtr, Args) -> Dyn::Object,
+    impl<D: ?Sized + DynCompatible> Select<D, Here> for D {
+        fn select(this: &D::Object) -> &D::Object {
+            this
+        }
+        fn select_pin(this: Pin<&mut D::Object>) -> Pin<&mut D::Object> {
+            this
+        }
     }
-
-    impl<Dyn: ?Sized + DynCompatible, Args> DynInit<Dyn, Args> {
-        pub unsafe fn new(
-            args: Args,
-            layout: fn() -> Layout,
-            init: fn(VoidPtr, Args) -> Dyn::Object,
-        ) -> Self {
-            Self { args, layout, init }
+    impl<A, B, D, I> Select<D, First<I>> for Both<A, B>
+    where
+        A: ?Sized + Select<D, I>,
+        B: ?Sized + DynCompatible,
+        D: ?Sized + DynCompatible,
+    {
+        fn select(this: &DynBoth<A, B>) -> &D::Object {
+            A::select(&this.a)
         }
-
-        pub fn layout(&self) -> Layout {
-            (self.layout)()
+        fn select_pin(this: Pin<&mut DynBoth<A, B>>) -> Pin<&mut D::Object> {
+            A::select_pin(this.project().0)
         }
-
-        pub unsafe fn init(self, slot: VoidPtr) -> Dyn::Object {
-            (self.init)(slot, self.args)
+    }
+    impl<A, B, D, I> Select<D, Second<I>> for Both<A, B>
+    where
+        A: ?Sized + DynCompatible,
+        B: ?Sized + Select<D, I>,
+        D: ?Sized + DynCompatible,
+    {
+        fn select(this: &DynBoth<A, B>) -> &D::Object {
+            B::select(&this.b)
+        }
+        fn select_pin(this: Pin<&mut DynBoth<A, B>>) -> Pin<&mut D::Object> {
+            B::select_pin(this.project().1)
         }
     }
 
-    pub struct DynBox<T: ?Sized + DynCompatible>(ManuallyDrop<T::Object>);
+    impl<A: ?Sized + DynCompatible, B: ?Sized + DynCompatible> DynBoth<A, B> {
+        /// Returns the object of `D`, e.g. `obj.get::<dyn Debug, _>()`.
+        pub fn get<D, I>(&self) -> &D::Object
+        where
+            D: ?Sized + DynCompatible,
+            Both<A, B>: Select<D, I, Object = Self>,
+        {
+            <Both<A, B>>::select(self)
+        }
+
+        /// Returns the pinned object of `D`, through which the methods taking
+        /// `self: Pin<&mut Self>` are called.
+        pub fn get_pin<D, I>(self: Pin<&mut Self>) -> Pin<&mut D::Object>
+        where
+            D: ?Sized + DynCompatible,
+            Both<A, B>: Select<D, I, Object = Self>,
+        {
+            <Both<A, B>>::select_pin(self)
+        }
 
-    impl<T: ?Sized + DynCompatible> DynBox<T> {
-        pub fn init<Args>(init: DynInit<T, Args>) -> Self {
+        fn project(self: Pin<&mut Self>) -> (Pin<&mut A::Object>, Pin<&mut B::Object>) {
+            // SAFETY: both objects are structurally pinned, and neither of
+            // them moves the data they point to.
             unsafe {
-                let layout = init.layout();
-                let slot = NonNull::new(std::alloc::alloc(layout))
-                    .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
-                let obj = init.init(slot.cast());
-                Self(ManuallyDrop::new(obj))
+                let this = self.get_unchecked_mut();
+                (
+                    Pin::new_unchecked(&mut this.a),
+                    Pin::new_unchecked(&mut *this.b),
+                )
             }
         }
     }
-    impl<T: ?Sized + DynCompatible> Unpin for DynBox<T> {}
-    impl<T: ?Sized + DynCompatible> Deref for DynBox<T> {
-        type Target = T::Object;
-        fn deref(&self) -> &Self::Target {
-            &self.0
+
+    // SAFETY: the objects are pointers to the same data along with their
+    // vtables, none of which is `Send` unless its `DynCompatible` impl vouches
+    // for the data, as `dyn Send` does. So `B::Object: Send` means the data is
+    // `Send`, and so is `a`, which points to it as well. Only `B` is looked at,
+    // so `dyn Send` goes last, e.g. `Both<A, Both<B, dyn Send>>`.
+    unsafe impl<A, B> Send for DynBoth<A, B>
+    where
+        A: ?Sized + DynCompatible,
+        B: ?Sized + DynCompatible<Object: Send>,
+    {
+    }
+
+    impl<A, B> Future for DynBoth<A, B>
+    where
+        A: ?Sized + DynCompatible<Object: Future>,
+        B: ?Sized + DynCompatible,
+    {
+        type Output = <A::Object as Future>::Output;
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            self.project().0.poll(cx)
         }
     }
-    impl<T: ?Sized + DynCompatible> DerefMut for DynBox<T> {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.0
+
+    dyn_trait! {
+        /// A task that can be told to stop early.
+        pub trait Cancel: dyn DynCancel, mod cancel_vtable {
+            fn cancel(self: Pin<&mut Self>);
+            fn is_cancelled(&self) -> bool;
         }
     }
-    impl<T: ?Sized + DynCompatible> Drop for DynBox<T> {
-        fn drop(&mut self) {
-            unsafe {
-                let obj = ManuallyDrop::take(&mut self.0);
-                let ptr = T::data(&obj);
-                let layout = T::layout(&obj);
-                drop(obj);
-                std::alloc::dealloc(ptr.as_ptr().cast(), layout);
+
+    pub async fn test_dyn_multi() {
+        #[derive(Debug)]
+        struct Job {
+            polls: u32,
+            cancelled: bool,
+        }
+        impl Future for Job {
+            type Output = Option<u32>;
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                if self.cancelled {
+                    return Poll::Ready(None);
+                }
+                self.polls += 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+        impl Cancel for Job {
+            fn cancel(mut self: Pin<&mut Self>) {
+                self.cancelled = true;
+            }
+            fn is_cancelled(&self) -> bool {
+                self.cancelled
             }
         }
+
+        type DynJob = Both<
+            dyn Future<Output = Option<u32>>,
+            Both<dyn fmt::Debug, Both<dyn Cancel, dyn Send>>,
+        >;
+        let init = unsafe {
+            DynInit::<DynJob, _>::new((), Layout::new::<Job>, |slot, ()| {
+                slot.cast().write(Job {
+                    polls: 0,
+                    cancelled: false,
+                });
+                <Job as CoerceDyn<DynJob>>::construct(slot)
+            })
+        };
+        let job = DynBox::init(init);
+        assert_eq!(
+            format!("{:?}", job.get::<dyn fmt::Debug, _>()),
+            "Job { polls: 0, cancelled: false }"
+        );
+
+        // the handle is `Send` as the job is
+        let mut job = std::thread::spawn(move || {
+            let mut job = job;
+            let poll = pollster::block_on(std::future::poll_fn(|cx| {
+                Poll::Ready(Pin::new(&mut job).poll(cx))
+            }));
+            assert_eq!(poll, Poll::Pending);
+            job
+        })
+        .join()
+        .unwrap();
+
+        assert!(!job.get::<dyn Cancel, _>().is_cancelled());
+        job.as_pin_mut().get_pin::<dyn Cancel, _>().cancel();
+        assert!(job.get::<dyn Cancel, _>().is_cancelled());
+        assert_eq!(
+            format!("{:?}", job.get::<dyn fmt::Debug, _>()),
+            "Job { polls: 1, cancelled: true }"
+        );
+        assert_eq!((&mut job).await, None);
+        println!("test_dyn_multi pass");
     }
 }
-pub use dyn_init::*;
+pub use dyn_multi::*;
 
 // =========== 例子 ===========
 mod example {
@@ -276,26 +552,45 @@ mod example {
                         let foo = <Self as Async>::foo;
                         let val = unsafe { foo(this.cast().as_mut(), arg) };
                         unsafe { return_type_cast_ptr(&foo, slot).write(val) }
-                        unsafe { return_type_object(&foo, slot) }
+                        unsafe {
+                            return_type_object::<_, _, dyn Future<Output = T::Item>>(&foo, slot)
+                        }
                     },
                 )
             }
         }
     }
+}
+use example::*;
 
-    pub unsafe fn return_type_object<I, F: Function<I>>(
-        _: &F,
-        data: VoidPtr,
-    ) -> <F::Output as DynCompatible>::Object
-    where
-        F::Output: DynCompatible,
-    {
-        <F::Output as DynCompatible>::construct(data)
+/// What a call site does with a future that doesn't fit on the stack.
+#[derive(Clone, Copy)]
+enum Fallback {
+    Heap,
+    /// Fails with a [`HeapFallback`] rather than allocating.
+    NoHeap,
+}
+
+fn heap_fallback<T: ?Sized + DynCompatible, Args>(
+    init: DynInit<T, Args>,
+    fallback: Fallback,
+) -> Result<DynBox<T>, HeapFallback> {
+    match fallback {
+        Fallback::Heap => {
+            println!("heap");
+            Ok(DynBox::init(init))
+        }
+        Fallback::NoHeap => Err(HeapFallback {
+            layout: init.layout(),
+        }),
     }
 }
-use example::*;
 
-async fn dynamic_dispatch<Item>(imp: &mut dyn DynAsync<Item = Item>, arg: String) -> Item {
+async fn dynamic_dispatch<Item>(
+    imp: &mut dyn DynAsync<Item = Item>,
+    arg: String,
+    fallback: Fallback,
+) -> Result<Item, HeapFallback> {
     let foo_init = imp.foo(arg);
     let layout = dbg!(foo_init.layout());
     let mut stack = [0u8; 64];
@@ -309,10 +604,33 @@ async fn dynamic_dispatch<Item>(imp: &mut dyn DynAsync<Item = Item>, arg: String
     // dbg!( start, end, byte_offset, slot, slot_end, stack.len(), layout.align(), layout.size());
     if slot >= start && slot_end <= end {
         println!("stack");
-        unsafe { foo_init.init(NonNull::new_unchecked(slot).cast()).await }
+        Ok(unsafe { foo_init.init(NonNull::new_unchecked(slot).cast()).await })
+    } else {
+        Ok(unsafe { Pin::new_unchecked(heap_fallback(foo_init, fallback)?) }.await)
+    }
+}
+
+// 输出较大时，直接写入调用方提供的位置，避免经过擦除层的多次拷贝
+async fn dynamic_dispatch_into<'a, Item>(
+    imp: &mut dyn DynAsync<Item = Item>,
+    arg: String,
+    out: &'a mut MaybeUninit<Item>,
+    fallback: Fallback,
+) -> Result<&'a mut Item, HeapFallback> {
+    let foo_init = imp.foo(arg);
+    let mut stack = [0u8; 64];
+
+    let start = &raw mut stack as *mut u8;
+    let end = start.wrapping_add(stack.len());
+    let slot = start.wrapping_add(start.align_offset(foo_init.layout().align()));
+    let slot_end = slot.wrapping_add(foo_init.layout().size());
+
+    if slot >= start && slot_end <= end {
+        let fut = std::pin::pin!(unsafe { foo_init.init(NonNull::new_unchecked(slot).cast()) });
+        Ok(fut.write_into(out).await)
     } else {
-        println!("heap");
-        unsafe { Pin::new_unchecked(DynBox::init(foo_init)) }.await
+        let mut fut = unsafe { Pin::new_unchecked(heap_fallback(foo_init, fallback)?) };
+        Ok(fut.as_mut().write_into(out).await)
     }
 }
 
@@ -335,6 +653,85 @@ fn main() {
     pollster::block_on(run());
 }
 
+/// A future that stays pending for the given number of polls.
+#[derive(Debug)]
+struct Countdown(u32);
+impl Future for Countdown {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 == 0 {
+            return Poll::Ready(());
+        }
+        self.0 -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+impl FusedFuture for Countdown {
+    fn is_terminated(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+async fn test_future_vtable() {
+    // async fn futures are neither `Debug` nor fused, but still have a name
+    let mut stack = MaybeUninit::<[u64; 8]>::uninit();
+    let foo_init = DynAsync::foo(&mut AppendYay, "foo".to_owned());
+    assert!(foo_init.layout().size() <= size_of_val(&stack));
+    let mut fut = std::pin::pin!(unsafe { foo_init.init(NonNull::from(&mut stack).cast()) });
+    assert!(fut.type_name().contains("foo"));
+    let debug = format!("{fut:?}");
+    assert!(
+        debug.starts_with("DynFuture { type: ")
+            && debug.ends_with("terminated: false, future: .. }")
+    );
+    assert_eq!(fut.as_mut().await, "foo, yay!");
+    assert!(fut.is_terminated());
+
+    // so does one whose output is written in place
+    let mut stack = MaybeUninit::<[u64; 8]>::uninit();
+    let foo_init = DynAsync::foo(&mut AppendYay, "bar".to_owned());
+    let mut fut = std::pin::pin!(unsafe { foo_init.init(NonNull::from(&mut stack).cast()) });
+    let mut out = MaybeUninit::uninit();
+    let item = fut.as_mut().write_into(&mut out).await;
+    assert_eq!(std::mem::take(item), "bar, yay!");
+    assert!(fut.is_terminated());
+    unsafe { out.assume_init_drop() };
+
+    let init = unsafe {
+        DynInit::<dyn Future<Output = ()>, _>::new(2, Layout::new::<Countdown>, |slot, n| {
+            slot.cast().write(Countdown(n));
+            DynFuture::construct_debug_fused::<Countdown>(slot)
+        })
+    };
+    let mut fut = DynBox::init(init);
+    assert_eq!(
+        format!("{fut:?}"),
+        format!(
+            "DynBox(DynFuture {{ type: {:?}, terminated: false, future: Countdown(2) }})",
+            std::any::type_name::<Countdown>(),
+        )
+    );
+    // asks the future itself before it's polled to completion
+    std::future::poll_fn(|cx| {
+        assert!(Pin::new(&mut fut).poll(cx).is_pending());
+        assert!(Pin::new(&mut fut).poll(cx).is_pending());
+        Poll::Ready(())
+    })
+    .await;
+    assert!(fut.is_terminated());
+    (&mut fut).await;
+    println!("test_future_vtable pass");
+}
+
+struct Large;
+impl Async for Large {
+    type Item = [u64; 512];
+    async fn foo(&mut self, args: String) -> Self::Item {
+        [args.len() as u64; 512]
+    }
+}
+
 struct BorrowIt<'a>(&'a str);
 impl<'a> Async for BorrowIt<'a> {
     type Item = &'a str;
@@ -343,16 +740,40 @@ impl<'a> Async for BorrowIt<'a> {
     }
 }
 
+struct HoldBuf;
+impl Async for HoldBuf {
+    type Item = usize;
+    async fn foo(&mut self, args: String) -> Self::Item {
+        // Use the large buffer across await point to make the future large.
+        let buf = [0u8; 128];
+        async {}.await;
+        std::hint::black_box(&buf).len() + args.len()
+    }
+}
+
 async fn run() {
-    test_return_type_layout();
+    test_future_vtable().await;
+    test_dyn_trait().await;
+    test_dyn_multi().await;
 
-    dynamic_dispatch(&mut PrintYay, "foo".to_owned()).await;
+    dynamic_dispatch(&mut PrintYay, "foo".to_owned(), Fallback::Heap)
+        .await
+        .unwrap();
 
-    let item = dynamic_dispatch(&mut AppendYay, "foo".to_owned()).await;
-    assert_eq!(item, "foo, yay!");
+    let item = dynamic_dispatch(&mut AppendYay, "foo".to_owned(), Fallback::Heap).await;
+    assert_eq!(item.unwrap(), "foo, yay!");
 
     let s = String::from(":)");
     let mut borrow_it = BorrowIt(&s);
-    let item = dynamic_dispatch(&mut borrow_it, Default::default()).await;
-    assert_eq!(item, ":)");
+    let item = dynamic_dispatch(&mut borrow_it, Default::default(), Fallback::Heap).await;
+    assert_eq!(item.unwrap(), ":)");
+
+    let mut out = MaybeUninit::uninit();
+    let item = dynamic_dispatch_into(&mut Large, "foo".to_owned(), &mut out, Fallback::Heap).await;
+    assert_eq!(*item.unwrap(), [3; 512]);
+
+    let item = dynamic_dispatch(&mut HoldBuf, "foo".to_owned(), Fallback::Heap).await;
+    assert_eq!(item.unwrap(), 131);
+    let item = dynamic_dispatch(&mut HoldBuf, "foo".to_owned(), Fallback::NoHeap).await;
+    assert!(item.is_err());
 }
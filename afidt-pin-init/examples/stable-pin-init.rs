@@ -2,276 +2,1066 @@
 //! stable Rust by avoiding Pointer Metadata APIs and reinventing trait objects.
 //!
 //! Original athuor is [@loichyan](https://github.com/loichyan).
+//!
+//! The placement machinery, i.e. constructors, their containers and
+//! combinators, lives in the `afidt` crate, on top of which this file builds
+//! erased async traits.
 #![allow(unsafe_op_in_unsafe_fn)]
 #![allow(clippy::missing_safety_doc)]
 
 use std::alloc::Layout;
-use std::convert::Infallible;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::ptr::NonNull;
 
-// =========== Retrieve return type from arbitrary functions ===========
-mod function {
-    use std::any::Any;
+use afidt::*;
+
+// =========== Compose constructors ===========
+mod combinator {
+    pub use super::*;
+
+    pub async fn test_combinator() {
+        let add = async |x: u32| x + 1;
+        let add: &dyn DynAsyncFn<(u32,), u32> = &add;
+        let never = async |_: u32| std::future::pending::<u32>().await;
+        let never: &dyn DynAsyncFn<(u32,), u32> = &never;
+
+        // the composed future lives in a single slot, and its layout is
+        // exactly the one of the composed object
+        let mut stack = std::pin::pin!([0u8; 256]);
+        let fut = add.call((1,)).map(|x| x * 10);
+        let layout = fut.layout();
+        assert!(layout.size() > add.call_layout().size());
+        let fut = fut.buffered(stack.as_mut());
+        assert_eq!(Layout::for_value(&*fut), layout);
+        assert_eq!(fut.await, 20);
 
-    use super::*;
+        // so are the futures chained or joined with it
+        let fut = add.call((1,)).then(add.call_layout(), |x| add.call((x,)));
+        let fut = fut.map(|x| x * 10);
+        assert_eq!(fut.buffered(stack.as_mut()).await, 30);
+        let fut = add.call((1,)).join(add.call((2,)).map(|x| x * 10));
+        let layout = fut.layout();
+        let fut = fut.buffered(stack.as_mut());
+        assert_eq!(Layout::for_value(&*fut), layout);
+        assert_eq!(fut.await, (2, 30));
+        let fut = add.call((1,)).join(never.call((1,)).timeout(async {}));
+        assert_eq!(fut.boxed().await, (2, Err(Elapsed)));
 
-    pub trait Function<Input> {
-        type Output;
+        let fut = add.call((1,)).timeout(std::future::pending());
+        assert_eq!(fut.buffered(stack.as_mut()).await, Ok(2));
+        let fut = never.call((1,)).timeout(async {});
+        assert_eq!(fut.buffered(stack.as_mut()).await, Err(Elapsed));
+
+        // composed constructors can still be awaited directly
+        let (a, b) = add
+            .call((1,))
+            .map(|x| x * 10)
+            .join(never.call((1,)).timeout(async {}))
+            .await;
+        assert_eq!((a, b), (20, Err(Elapsed)));
+        assert_eq!(dyn_await!(add.call((1,)).map(|x| x + 1), stack = 64), 3);
+        let fut = add.call((1,)).map(|x| x + 1).no_heap();
+        assert_eq!(fut.await, Ok(3));
+        println!("test_combinator pass");
     }
+}
+pub use combinator::*;
 
-    macro_rules! impl_function {
-        ($($i:ident),* -> $o:ident) => {
-            impl<Fn, $($i,)* $o> Function<($($i,)*)> for Fn
-            where
-                Fn: FnOnce($($i,)*) -> $o,
-            {
-                type Output = $o;
+// =========== Retry constructors in place ===========
+mod retry {
+    use std::future::ready;
+
+    pub use super::*;
+
+    pub async fn test_retry() {
+        let mut calls = 0;
+        let mut flaky = async |x: u32| {
+            calls += 1;
+            if calls % 3 == 0 {
+                Ok(x + calls)
+            } else {
+                Err(calls)
             }
         };
+        let flaky: &mut dyn DynAsyncFnMut<(u32,), Result<u32, u32>> = &mut flaky;
+
+        // all attempts are made in the same slot
+        let mut stack = std::pin::pin!([0u8; 256]);
+        let fut = flaky.call_mut((1,)).retry(Attempts(5));
+        assert!(fits_in_buffer(fut.layout(), 256));
+        assert_eq!(fut.buffered(stack.as_mut()).await, Ok(4));
+        let fut = flaky.call_mut((1,)).retry(Attempts(2));
+        assert_eq!(fut.buffered(stack.as_mut()).await, Err(5));
+
+        let mut retried = 0;
+        let policy = |attempts: u32, output: &Result<u32, u32>| {
+            retried = attempts;
+            output.is_err()
+        };
+        assert_eq!(flaky.call_mut((1,)).retry(policy).boxed().await, Ok(7));
+        assert_eq!(retried, 1);
+
+        let mut delays = Vec::new();
+        let policy = Backoff::new(Attempts(3), |attempts| {
+            delays.push(attempts);
+            ready(())
+        });
+        let fut = flaky.call_mut((1,)).retry(policy);
+        assert_eq!(try_dyn_await!(fut, stack = 256), Ok(Ok(10)));
+        assert_eq!(delays, [1, 2]);
+        println!("test_retry pass");
     }
-    impl_function!(A                -> R);
-    impl_function!(A, B             -> R);
-    impl_function!(A, B, C          -> R);
-    impl_function!(A, B, C, D       -> R);
-    impl_function!(A, B, C, D, E    -> R);
-    impl_function!(A, B, C, D, E, F -> R);
+}
+pub use retry::*;
 
-    pub const fn return_type_dangling_ptr<I, F: Function<I>>(_: &F) -> *mut F::Output {
-        std::ptr::dangling_mut()
+// =========== Queue deferred calls ===========
+mod call_queue {
+    use std::marker::PhantomPinned;
+    use std::mem::{self, MaybeUninit};
+    use std::task::{Context, Poll};
+
+    pub use super::*;
+
+    type DynFuture<'a, T> = dyn 'a + Future<Output = T>;
+
+    /// A queue of pending calls whose futures all return `T`.
+    ///
+    /// Each call first stores its constructor in an inline ring buffer of `LEN`
+    /// bytes. When the queue is polled, the future is constructed where the
+    /// constructor was, and is driven along with the others. At most `N` calls
+    /// can be queued at the same time.
+    pub struct CallQueue<'a, T, const N: usize, const LEN: usize> {
+        entries: [Option<Entry<'a, T>>; N],
+        head: usize,
+        // entries in the ring, including completed ones behind a running one
+        used: usize,
+        remaining: usize,
+        buf: [MaybeUninit<u8>; LEN],
+        _pinned: PhantomPinned,
     }
 
-    pub const fn return_type_layout<I, F: Function<I>>(_: &F) -> Layout {
-        Layout::new::<F::Output>()
+    struct Entry<'a, T> {
+        offset: usize,
+        size: usize,
+        call: Call<'a, T>,
     }
 
-    pub const fn return_type_cast_ptr<I, F: Function<I>>(
-        _: &F,
-        ptr: VoidPtr,
-    ) -> NonNull<F::Output> {
-        ptr.cast()
+    enum Call<'a, T> {
+        Pending {
+            emplace: unsafe fn(VoidPtr) -> NonNull<DynFuture<'a, T>>,
+            drop: unsafe fn(VoidPtr),
+        },
+        Running(NonNull<DynFuture<'a, T>>),
+        Done,
     }
 
-    pub fn test_return_type_layout() {
-        fn f1(_: usize, _: usize) -> usize {
-            todo!()
+    impl<'a, T, const N: usize, const LEN: usize> CallQueue<'a, T, N, LEN> {
+        pub fn new() -> Self {
+            Self {
+                entries: [const { None }; N],
+                head: 0,
+                used: 0,
+                remaining: 0,
+                buf: [MaybeUninit::uninit(); LEN],
+                _pinned: PhantomPinned,
+            }
+        }
+
+        /// The number of calls that haven't completed yet.
+        pub fn len(&self) -> usize {
+            self.remaining
         }
-        fn f2(_: &str) -> &str {
-            todo!()
+
+        pub fn is_empty(&self) -> bool {
+            self.remaining == 0
+        }
+
+        /// Queues a call, or gives it back if the queue is full.
+        pub fn push<Args: 'a>(
+            self: Pin<&mut Self>,
+            call: PinConstructor<DynFuture<'a, T>, Args>,
+        ) -> Result<(), PinConstructor<DynFuture<'a, T>, Args>> {
+            // SAFETY: nothing is moved out of the queue.
+            let this = unsafe { self.get_unchecked_mut() };
+            let constructor = Layout::new::<PinConstructor<DynFuture<'a, T>, Args>>();
+            let layout = max_layout(&[constructor, call.layout()]);
+            if this.used == N {
+                return Err(call);
+            }
+            let Some(offset) = this.allocate(layout) else {
+                return Err(call);
+            };
+            unsafe {
+                let slot = NonNull::from(&mut this.buf).cast::<u8>().add(offset);
+                slot.cast().write(call);
+            }
+            this.entries[(this.head + this.used) % N] = Some(Entry {
+                offset,
+                size: layout.size(),
+                call: Call::Pending {
+                    emplace: |slot| unsafe {
+                        // The constructor is moved out before the future is
+                        // written over it.
+                        let call = slot.cast::<PinConstructor<DynFuture<'a, T>, Args>>().read();
+                        call.unpinned().emplace(slot)
+                    },
+                    drop: |slot| unsafe {
+                        slot.cast::<PinConstructor<DynFuture<'a, T>, Args>>()
+                            .drop_in_place()
+                    },
+                },
+            });
+            this.used += 1;
+            this.remaining += 1;
+            Ok(())
         }
-        fn f3(_: String, _: Vec<u8>, _: &dyn Any) -> Box<dyn Any> {
-            todo!()
+
+        /// Finds room for `layout` after the newest entry, wrapping around to
+        /// the start of the buffer if needed.
+        fn allocate(&self, layout: Layout) -> Option<usize> {
+            let regions = if self.used == 0 {
+                [(0, LEN), (0, 0)]
+            } else {
+                let first = self.entry(0);
+                let last = self.entry(self.used - 1);
+                let (start, end) = (first.offset, last.offset + last.size);
+                if start < end {
+                    [(end, LEN), (0, start)]
+                } else {
+                    [(end, start), (0, 0)]
+                }
+            };
+            let base = self.buf.as_ptr() as usize;
+            regions.into_iter().find_map(|(start, end)| {
+                let offset = (base + start).next_multiple_of(layout.align()) - base;
+                (offset + layout.size() <= end).then_some(offset)
+            })
         }
-        fn f4(_: usize, _: usize) -> Infallible {
-            todo!()
+
+        fn entry(&self, i: usize) -> &Entry<'a, T> {
+            self.entries[(self.head + i) % N].as_ref().unwrap()
         }
 
-        assert_eq!(Layout::new::<usize>(), return_type_layout(&f1));
-        assert_eq!(Layout::new::<&str>(), return_type_layout(&f2));
-        assert_eq!(Layout::new::<Box<dyn Any>>(), return_type_layout(&f3));
-        assert_eq!(Layout::new::<Infallible>(), return_type_layout(&f4));
-        println!("test_return_type_layout pass");
+        /// Constructs pending futures, and polls them until one completes.
+        pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+            // SAFETY: futures are pinned in `buf` and dropped in place.
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.used == 0 {
+                return Poll::Ready(None);
+            }
+            let base = NonNull::from(&mut this.buf).cast::<u8>();
+            let mut output = None;
+            for i in 0..this.used {
+                let entry = this.entries[(this.head + i) % N].as_mut().unwrap();
+                let slot = unsafe { base.add(entry.offset).cast() };
+                // The entry is marked as done while the constructor or the
+                // future is moved out, so that a panic won't drop it twice.
+                if let Call::Pending { emplace, .. } = entry.call {
+                    entry.call = Call::Done;
+                    let guard = UncountOnUnwind(&mut this.remaining);
+                    entry.call = Call::Running(unsafe { emplace(slot) });
+                    mem::forget(guard);
+                }
+                let Call::Running(mut fut) = entry.call else {
+                    continue;
+                };
+                if let Poll::Ready(out) = unsafe { Pin::new_unchecked(fut.as_mut()) }.poll(cx) {
+                    entry.call = Call::Done;
+                    this.remaining -= 1;
+                    unsafe { fut.drop_in_place() };
+                    output = Some(out);
+                    break;
+                }
+            }
+            // reclaim the space of completed calls at the front
+            while this.used > 0 && matches!(this.entry(0).call, Call::Done) {
+                this.entries[this.head] = None;
+                this.head = (this.head + 1) % N;
+                this.used -= 1;
+            }
+            match output {
+                Some(out) => Poll::Ready(Some(out)),
+                None => Poll::Pending,
+            }
+        }
+
+        /// Waits for the next call to complete, or returns `None` if the queue
+        /// is empty.
+        pub async fn next(mut self: Pin<&mut Self>) -> Option<T> {
+            std::future::poll_fn(|cx| self.as_mut().poll_next(cx)).await
+        }
+    }
+
+    /// Uncounts a call whose constructor unwinds, as nothing is left of it.
+    struct UncountOnUnwind<'a>(&'a mut usize);
+    impl Drop for UncountOnUnwind<'_> {
+        fn drop(&mut self) {
+            *self.0 -= 1;
+        }
     }
-}
-pub use function::*;
 
-// =========== Construct `dyn` object in arbitaray containers ===========
-mod dyn_init {
-    use std::ops::{Deref, DerefMut};
+    impl<T, const N: usize, const LEN: usize> Default for CallQueue<'_, T, N, LEN> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
 
-    pub use super::*;
+    impl<T, const N: usize, const LEN: usize> Stream for CallQueue<'_, T, N, LEN> {
+        type Item = T;
 
-    pub struct Constructor<Dyn: ?Sized, Args> {
-        layout: Layout,
-        args: Args,
-        init: unsafe fn(VoidPtr, Args) -> NonNull<Dyn>,
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            CallQueue::poll_next(self, cx)
+        }
+    }
+
+    impl<T, const N: usize, const LEN: usize> Drop for CallQueue<'_, T, N, LEN> {
+        fn drop(&mut self) {
+            let base = NonNull::from(&mut self.buf).cast::<u8>();
+            for entry in self.entries.iter_mut().filter_map(Option::take) {
+                let slot = unsafe { base.add(entry.offset).cast() };
+                match entry.call {
+                    Call::Pending { drop, .. } => unsafe { drop(slot) },
+                    Call::Running(fut) => unsafe { fut.drop_in_place() },
+                    Call::Done => {}
+                }
+            }
+        }
     }
 
-    pub type VoidPtr = NonNull<Void>;
-    pub enum Void {}
+    pub async fn test_call_queue() {
+        let sent = std::cell::RefCell::new(Vec::new());
+        let sms = async |phone: String, code: u32| {
+            sent.borrow_mut().push(format!("sms {phone}: {code}"));
+            phone.len()
+        };
+        let email = async |addr: String| {
+            // Use the large buffer across await point to make the future large.
+            let large = [0u8; 64];
+            async {}.await;
+            sent.borrow_mut().push(format!("email {addr}"));
+            addr.len() + large.len()
+        };
+        let sms: &dyn DynAsyncFn<(String, u32), usize> = &sms;
+        let email: &dyn DynAsyncFn<(String,), usize> = &email;
 
-    impl<Dyn: ?Sized, Args> Constructor<Dyn, Args> {
-        pub unsafe fn new(
-            layout: Layout,
-            args: Args,
-            init: unsafe fn(VoidPtr, Args) -> NonNull<Dyn>,
-        ) -> Self {
-            Self { layout, args, init }
+        let mut queue = std::pin::pin!(CallQueue::<usize, 4, 512>::new());
+        for (phone, code) in [("123", 1), ("4567", 2)] {
+            assert!(queue.as_mut().push(sms.call((phone.into(), code))).is_ok());
         }
+        assert!(queue.as_mut().push(email.call(("a@b".into(),))).is_ok());
+        assert_eq!(queue.len(), 3);
+        // nothing runs until the queue is driven
+        assert!(sent.borrow().is_empty());
 
-        pub fn layout(&self) -> Layout {
-            self.layout
+        let mut total = 0;
+        while let Some(n) = queue.as_mut().next().await {
+            total += n;
         }
+        assert_eq!(total, 3 + 4 + 3 + 64);
+        assert_eq!(*sent.borrow(), ["sms 123: 1", "sms 4567: 2", "email a@b"]);
 
-        /// Constructs the `dyn` object in the supplied slot.
-        ///
-        /// # Safety
-        ///
-        /// 1. `slot` must have enough space to fit the [`layout`] of the object.
-        /// 2. `slot` must be exclusive for this construction.
-        ///
-        /// [`layout`]: Self::layout
-        pub unsafe fn emplace(self, slot: VoidPtr) -> NonNull<Dyn> {
-            (self.init)(slot, self.args)
+        // keep the queue busy so that the ring buffer wraps around
+        sent.borrow_mut().clear();
+        let mut calls = 0;
+        for round in 0..8 {
+            while queue
+                .as_mut()
+                .push(email.call((round.to_string(),)))
+                .is_ok()
+            {
+                calls += 1;
+            }
+            let call = sms.call((round.to_string(), round));
+            match queue.as_mut().push(call) {
+                Ok(()) => calls += 1,
+                // a full queue hands the call back, which can still be awaited
+                Err(call) => assert_eq!(call.no_heap().await, Ok(1)),
+            }
+            queue.as_mut().next().await.unwrap();
         }
+        while queue.as_mut().next().await.is_some() {}
+        assert_eq!(sent.borrow().len(), calls + 8);
+        assert!(queue.is_empty());
 
-        pub fn init<C>(self, container: C) -> C::Ptr
-        where
-            C: Container<Dyn>,
+        // a completed call behind a running one is no longer counted
+        let gate = std::cell::Cell::new(false);
+        let gated = async || {
+            std::future::poll_fn(|_| match gate.get() {
+                true => Poll::Ready(0),
+                false => Poll::Pending,
+            })
+            .await
+        };
+        let gated: &dyn DynAsyncFn<(), usize> = &gated;
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let mut queue = std::pin::pin!(CallQueue::<usize, 4, 512>::new());
+        assert!(queue.as_mut().push(gated.call(())).is_ok());
+        assert!(queue.as_mut().push(sms.call(("12".into(), 0))).is_ok());
+        assert_eq!(queue.as_mut().poll_next(&mut cx), Poll::Ready(Some(2)));
+        assert_eq!(queue.len(), 1);
+        gate.set(true);
+        assert_eq!(queue.as_mut().poll_next(&mut cx), Poll::Ready(Some(0)));
+        assert!(queue.is_empty());
+
+        // a constructor panicking while it's moved out is dropped only once
+        struct Counted<'c>(&'c std::cell::Cell<u32>);
+        impl Drop for Counted<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+        let drops = std::cell::Cell::new(0);
+        let panicking = unsafe {
+            Constructor::<DynFuture<'_, usize>, _>::new(
+                Layout::new::<u8>(),
+                Counted(&drops),
+                |_, _| panic!("failed to construct"),
+            )
+        };
         {
-            container.init(self)
+            let mut queue = std::pin::pin!(CallQueue::<usize, 4, 512>::new());
+            assert!(queue.as_mut().push(panicking.pinned()).is_ok());
+            let hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(|_| {}));
+            let poll = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                queue.as_mut().poll_next(&mut cx)
+            }));
+            std::panic::set_hook(hook);
+            assert!(poll.is_err());
+            assert!(queue.is_empty());
         }
+        assert_eq!(drops.get(), 1);
+        println!("test_call_queue pass");
+    }
+}
+pub use call_queue::*;
 
-        pub fn try_init<C>(self, container: C) -> Result<C::Ptr, C::Err<Args>>
+// =========== Erase async closures ===========
+mod dyn_fn {
+    pub use super::*;
+
+    /// The constructor of a future returned from an erased async closure.
+    pub type DynAsyncFnConstructor<'a, Args, Output> =
+        PinConstructor<dyn 'a + Future<Output = Output>, (VoidPtr, Args)>;
+
+    /// An object safe [`AsyncFn`] whose futures live where the caller decides.
+    pub trait DynAsyncFn<Args, Output> {
+        fn call<'a>(&'a self, args: Args) -> DynAsyncFnConstructor<'a, Args, Output>
         where
-            C: Container<Dyn>,
-        {
-            container.try_init(self)
-        }
+            Args: 'a;
 
-        pub fn boxed(self) -> Box<Dyn> {
-            self.init(Boxed)
-        }
+        /// The layout of futures returned from [`call`](Self::call).
+        fn call_layout(&self) -> Layout;
 
-        pub fn buffered(self, buf: &mut [u8]) -> Buffered<Dyn> {
-            self.init(buf)
+        /// The layout fitting futures of all methods.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.call_layout()])
         }
+    }
+
+    /// An object safe [`AsyncFnMut`] whose futures live where the caller decides.
+    pub trait DynAsyncFnMut<Args, Output> {
+        fn call_mut<'a>(&'a mut self, args: Args) -> DynAsyncFnConstructor<'a, Args, Output>
+        where
+            Args: 'a;
+
+        /// The layout of futures returned from [`call_mut`](Self::call_mut).
+        fn call_mut_layout(&self) -> Layout;
 
-        pub fn try_buffered(self, buf: &mut [u8]) -> Result<Buffered<Dyn>, Self> {
-            self.try_init(buf)
+        /// The layout fitting futures of all methods.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.call_mut_layout()])
         }
+    }
+
+    /// The constructor of a future returned from an erased async closure that
+    /// can only be called once.
+    pub type DynAsyncFnOnceConstructor<'a, Args, Output> =
+        PinConstructor<dyn 'a + Future<Output = Output>, (Once, Args)>;
+
+    /// The closure taken by a [`DynAsyncFnOnceConstructor`].
+    ///
+    /// It isn't [`Clone`], so that the constructor can't be duplicated, e.g.
+    /// by [`retry`](PinConstructor::retry), and construct more than one future.
+    pub struct Once(VoidPtr);
 
-        pub fn pinned(self) -> PinConstructor<Dyn, Args> {
-            PinConstructor(self)
+    /// An object safe [`AsyncFnOnce`] whose futures live where the caller decides.
+    ///
+    /// A trait object can't be consumed by value, so this is implemented for
+    /// `Option<F>` instead, and the closure is taken out of it when the future
+    /// is constructed.
+    pub trait DynAsyncFnOnce<Args, Output> {
+        /// # Panics
+        ///
+        /// Panics if the closure has been taken by a previous call.
+        fn call_once<'a>(&'a mut self, args: Args) -> DynAsyncFnOnceConstructor<'a, Args, Output>
+        where
+            Args: 'a;
+
+        /// The layout of futures returned from [`call_once`](Self::call_once).
+        fn call_once_layout(&self) -> Layout;
+
+        /// The layout fitting futures of all methods.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.call_once_layout()])
         }
     }
 
-    /// A variant of [`Constructor`] that requires pinned pointers.
-    pub struct PinConstructor<Dyn: ?Sized, Args>(Constructor<Dyn, Args>);
-    impl<Dyn: ?Sized, Args> PinConstructor<Dyn, Args> {
-        pub fn boxed(self) -> Pin<Box<Dyn>> {
-            Box::into_pin(self.0.boxed())
+    macro_rules! impl_dyn_async_fn {
+        ($m:ident: $($i:ident $a:ident),* -> $o:ident) => {
+            // Named functions returning the futures of async closures,
+            // so that their layouts can be retrieved by `return_type_layout`.
+            mod $m {
+                use super::*;
+
+                pub fn call<'a, Fn, $($i: 'a,)* $o>(
+                    this: &'a Fn,
+                    $($a: $i,)*
+                ) -> impl 'a + Future<Output = $o>
+                where
+                    Fn: AsyncFn($($i,)*) -> $o,
+                {
+                    this($($a,)*)
+                }
+
+                pub fn call_mut<'a, Fn, $($i: 'a,)* $o>(
+                    this: &'a mut Fn,
+                    $($a: $i,)*
+                ) -> impl 'a + Future<Output = $o>
+                where
+                    Fn: AsyncFnMut($($i,)*) -> $o,
+                {
+                    this($($a,)*)
+                }
+
+                pub fn call_once<'a, Fn, $($i: 'a,)* $o>(
+                    this: Fn,
+                    $($a: $i,)*
+                ) -> impl 'a + Future<Output = $o>
+                where
+                    Fn: 'a + AsyncFnOnce($($i,)*) -> $o,
+                {
+                    this($($a,)*)
+                }
+            }
+
+            impl<Fn, $($i,)* $o> DynAsyncFn<($($i,)*), $o> for Fn
+            where
+                Fn: AsyncFn($($i,)*) -> $o,
+            {
+                fn call<'a>(
+                    &'a self,
+                    args: ($($i,)*),
+                ) -> DynAsyncFnConstructor<'a, ($($i,)*), $o>
+                where
+                    ($($i,)*): 'a,
+                {
+                    unsafe {
+                        Constructor::new(
+                            self.call_layout(),
+                            (NonNull::from(self).cast(), args),
+                            |slot, (this, ($($a,)*))| {
+                                let fun = $m::call::<Self, $($i,)* $o>;
+                                let slot = return_type_cast_ptr(&fun, slot);
+                                slot.write(fun(this.cast().as_ref(), $($a,)*));
+                                Emplaced::new(slot)
+                            },
+                        )
+                    }
+                    .pinned()
+                }
+
+                fn call_layout(&self) -> Layout {
+                    return_type_layout(&$m::call::<Self, $($i,)* $o>)
+                }
+            }
+
+            impl<Fn, $($i,)* $o> DynAsyncFnMut<($($i,)*), $o> for Fn
+            where
+                Fn: AsyncFnMut($($i,)*) -> $o,
+            {
+                fn call_mut<'a>(
+                    &'a mut self,
+                    args: ($($i,)*),
+                ) -> DynAsyncFnConstructor<'a, ($($i,)*), $o>
+                where
+                    ($($i,)*): 'a,
+                {
+                    unsafe {
+                        Constructor::new(
+                            self.call_mut_layout(),
+                            (NonNull::from(self).cast(), args),
+                            |slot, (this, ($($a,)*))| {
+                                let fun = $m::call_mut::<Self, $($i,)* $o>;
+                                let slot = return_type_cast_ptr(&fun, slot);
+                                slot.write(fun(this.cast().as_mut(), $($a,)*));
+                                Emplaced::new(slot)
+                            },
+                        )
+                    }
+                    .pinned()
+                }
+
+                fn call_mut_layout(&self) -> Layout {
+                    return_type_layout(&$m::call_mut::<Self, $($i,)* $o>)
+                }
+            }
+
+            impl<Fn, $($i,)* $o> DynAsyncFnOnce<($($i,)*), $o> for Option<Fn>
+            where
+                Fn: AsyncFnOnce($($i,)*) -> $o,
+            {
+                fn call_once<'a>(
+                    &'a mut self,
+                    args: ($($i,)*),
+                ) -> DynAsyncFnOnceConstructor<'a, ($($i,)*), $o>
+                where
+                    ($($i,)*): 'a,
+                {
+                    assert!(self.is_some(), "`call_once` called after the closure was taken");
+                    unsafe {
+                        Constructor::new(
+                            self.call_once_layout(),
+                            (Once(NonNull::from(self).cast()), args),
+                            |slot, (Once(this), ($($a,)*))| {
+                                let fun = $m::call_once::<Fn, $($i,)* $o>;
+                                let this = this.cast::<Self>().as_mut().take();
+                                let this = this.expect("the closure was taken by another call");
+                                let slot = return_type_cast_ptr(&fun, slot);
+                                slot.write(fun(this, $($a,)*));
+                                Emplaced::new(slot)
+                            },
+                        )
+                    }
+                    .pinned()
+                }
+
+                fn call_once_layout(&self) -> Layout {
+                    return_type_layout(&$m::call_once::<Fn, $($i,)* $o>)
+                }
+            }
+        };
+    }
+    impl_dyn_async_fn!(arity0:                             -> R);
+    impl_dyn_async_fn!(arity1: A a                         -> R);
+    impl_dyn_async_fn!(arity2: A a, B b                    -> R);
+    impl_dyn_async_fn!(arity3: A a, B b, C c               -> R);
+    impl_dyn_async_fn!(arity4: A a, B b, C c, D d          -> R);
+    impl_dyn_async_fn!(arity5: A a, B b, C c, D d, E e     -> R);
+
+    pub async fn test_dyn_async_fn() {
+        let offset = 10;
+        let handlers: Vec<Box<dyn DynAsyncFn<(u32,), u32>>> = vec![
+            Box::new(async |x: u32| x + 1),
+            Box::new(async move |x: u32| {
+                // Use the large buffer across await point to make the future large.
+                let large = [x; 64];
+                async {}.await;
+                large.iter().sum::<u32>() + offset
+            }),
+        ];
+        let mut stack = std::pin::pin!([0u8; 64]);
+        let mut sum = 0;
+        for handler in &handlers {
+            sum += match handler.call((1,)).try_buffered(stack.as_mut()) {
+                Ok(fut) => fut.await,
+                Err(c) => c.boxed().await,
+            };
         }
+        assert_eq!(sum, 2 + 74);
 
-        pub fn buffered(self, buf: Pin<&mut [u8]>) -> Pin<Buffered<Dyn>> {
-            self.0.init(buf)
+        // size the scratch buffer once before making any call
+        let layouts: Vec<_> = handlers.iter().map(|h| h.max_future_layout()).collect();
+        let layout = max_layout(&layouts);
+        let mut scratch = vec![0u8; layout.size() + layout.align() - 1];
+        for handler in &handlers {
+            let call = handler.call((1,));
+            assert_eq!(call.layout(), handler.call_layout());
+            sum += call.buffered(Pin::new(&mut scratch)).await;
         }
+        assert_eq!(sum, 2 * (2 + 74));
 
-        pub fn try_buffered(self, buf: Pin<&mut [u8]>) -> Result<Pin<Buffered<Dyn>>, Self> {
-            self.0.try_init(buf).map_err(Self)
+        // await directly: inline if small enough, or boxed
+        for handler in &handlers {
+            sum += handler.call((1,)).await;
         }
+        sum += handlers[1].call((1,)).inline_or_boxed::<512>().await;
+        assert_eq!(sum, 3 * (2 + 74) + 74);
+        assert_eq!(dyn_await!(handlers[0].call((1,)), stack = 64), 2);
+        assert_eq!(dyn_await!(handlers[1].call((1,)), stack = 64), 74);
+        // or without the heap fallback, where a large future is an error
+        assert_eq!(handlers[0].call((1,)).no_heap().await, Ok(2));
+        let layout = handlers[1].call_layout();
+        let err = Err(HeapFallback { layout });
+        assert_eq!(handlers[1].call((1,)).no_heap().await, err);
 
-        pub fn unpinned(self) -> Constructor<Dyn, Args> {
-            self.0
+        let mut count = 0;
+        let mut counter = async |n: u32| {
+            count += n;
+            count
+        };
+        let counter: &mut dyn DynAsyncFnMut<(u32,), u32> = &mut counter;
+        counter.call_mut((1,)).buffered(stack.as_mut()).await;
+        counter.call_mut((2,)).buffered(stack.as_mut()).await;
+        assert_eq!(count, 3);
+
+        let s = String::from("once");
+        let mut once = Some(async move || s);
+        let once: &mut dyn DynAsyncFnOnce<(), String> = &mut once;
+        assert_eq!(once.call_once(()).buffered(stack.as_mut()).await, "once");
+
+        // only constructors with `Clone` args can be retried, which those of
+        // `call_once` aren't, as the closure is taken by the first attempt
+        struct Probe<T>(PhantomData<T>);
+        trait NotClone {
+            const CLONE: bool = false;
         }
+        impl<T> NotClone for Probe<T> {}
+        impl<T: Clone> Probe<T> {
+            const CLONE: bool = true;
+        }
+        const { assert!(Probe::<(VoidPtr, (u32,))>::CLONE) };
+        const { assert!(!Probe::<(Once, (u32,))>::CLONE) };
+        println!("test_dyn_async_fn pass");
     }
+}
+pub use dyn_fn::*;
 
-    /// A one-time container used to construct `dyn` objects.
-    pub unsafe trait Container<Dyn: ?Sized>: Sized {
-        type Ptr;
-        type Err<Args>;
+// =========== Erase async iterators ===========
+mod async_iter {
+    use std::marker::PhantomPinned;
+    use std::task::{Context, Poll};
 
-        fn init<Args>(self, constructor: Constructor<Dyn, Args>) -> Self::Ptr {
-            self.try_init(constructor)
-                .unwrap_or_else(|_| panic!("failed to initialize"))
-        }
+    pub use super::*;
 
-        fn try_init<Args>(
-            self,
-            constructor: Constructor<Dyn, Args>,
-        ) -> Result<Self::Ptr, Self::Err<Args>>;
+    #[allow(async_fn_in_trait)]
+    pub trait AsyncIterator {
+        type Item;
+
+        async fn next(&mut self) -> Option<Self::Item>;
     }
 
-    pub struct Boxed;
-    unsafe impl<Dyn: ?Sized> Container<Dyn> for Boxed {
-        type Ptr = Box<Dyn>;
-        type Err<Args> = Infallible;
+    pub trait DynAsyncIterator {
+        type Item;
 
-        fn try_init<Args>(
-            self,
-            constructor: Constructor<Dyn, Args>,
-        ) -> Result<Self::Ptr, Self::Err<Args>> {
-            let layout = constructor.layout();
-            let slot = match layout.size() {
-                0 => panic!("zero sized type is not supported"),
-                // SAFETY: `layout` is non-zero in size,
-                _ => unsafe { NonNull::new(std::alloc::alloc(layout)) }
-                    .unwrap_or_else(|| std::alloc::handle_alloc_error(layout)),
-            };
+        fn next<'a>(
+            &'a mut self,
+        ) -> PinConstructor<dyn 'a + Future<Output = Option<Self::Item>>, VoidPtr>;
+
+        fn next_layout(&self) -> Layout;
+
+        /// The layout fitting futures of all methods.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.next_layout()])
+        }
+    }
+
+    impl<T: AsyncIterator + Sized> DynAsyncIterator for T {
+        type Item = T::Item;
+
+        fn next<'a>(
+            &'a mut self,
+        ) -> PinConstructor<dyn 'a + Future<Output = Option<Self::Item>>, VoidPtr> {
             unsafe {
-                let ptr = constructor.emplace(slot.cast());
-                Ok(Box::from_raw(ptr.as_ptr()))
+                Constructor::new(
+                    self.next_layout(),
+                    NonNull::from(self).cast(),
+                    |slot, this| {
+                        let fun = <Self as AsyncIterator>::next;
+                        let slot = return_type_cast_ptr(&fun, slot);
+                        slot.write(fun(this.cast().as_mut()));
+                        Emplaced::new(slot)
+                    },
+                )
             }
+            .pinned()
+        }
+
+        fn next_layout(&self) -> Layout {
+            return_type_layout(&<Self as AsyncIterator>::next)
         }
     }
 
-    pub struct Buffered<'a, Dyn: ?Sized>(NonNull<Dyn>, PhantomData<&'a mut [u8]>);
-    impl<Dyn: ?Sized> Drop for Buffered<'_, Dyn> {
-        fn drop(&mut self) {
-            unsafe { self.0.drop_in_place() }
+    /// The poll-based shape of an async iterator, i.e. `futures::Stream`.
+    pub trait Stream {
+        type Item;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+    }
+
+    /// Drives a [`DynAsyncIterator`] as a [`Stream`].
+    ///
+    /// The in-flight `next()` future is kept in an inline buffer of `N` bytes
+    /// which is reused for every item, so no item allocates.
+    pub struct InlineStream<'a, I: ?Sized + DynAsyncIterator, const N: usize> {
+        iter: NonNull<I>,
+        // Borrows `buf` rather than `'static`, which is fine as `buf` is
+        // pinned along with `self` and outlives the future.
+        next: Option<NextFuture<'a, I::Item>>,
+        buf: [u8; N],
+        _marker: PhantomData<&'a mut I>,
+        _pinned: PhantomPinned,
+    }
+
+    type NextFuture<'a, Item> = Pin<Buffered<'static, dyn 'a + Future<Output = Option<Item>>>>;
+
+    impl<'a, I: ?Sized + DynAsyncIterator, const N: usize> InlineStream<'a, I, N> {
+        /// Fails if the `next()` future of `iter` doesn't fit in `N` bytes.
+        pub fn new(iter: &'a mut I) -> Result<Self, HeapFallback> {
+            let layout = iter.next_layout();
+            if !fits_in_buffer(layout, N) {
+                return Err(HeapFallback { layout });
+            }
+            Ok(Self {
+                iter: NonNull::from(iter),
+                next: None,
+                buf: [0; N],
+                _marker: PhantomData,
+                _pinned: PhantomPinned,
+            })
         }
     }
-    impl<Dyn: ?Sized> Deref for Buffered<'_, Dyn> {
-        type Target = Dyn;
-        fn deref(&self) -> &Self::Target {
-            unsafe { self.0.as_ref() }
+
+    impl<I: ?Sized + DynAsyncIterator, const N: usize> Stream for InlineStream<'_, I, N> {
+        type Item = I::Item;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            let this = unsafe { self.get_unchecked_mut() };
+            if this.next.is_none() {
+                // SAFETY: the iterator is only borrowed by the in-flight future,
+                // and `buf` doesn't move until `self` is dropped.
+                let iter = unsafe { &mut *this.iter.as_ptr() };
+                let buf = unsafe { Pin::new_unchecked(&mut *(&mut this.buf[..] as *mut [u8])) };
+                // `new` has checked that it fits.
+                this.next = Some(iter.next().buffered(buf));
+            }
+            let poll = this.next.as_mut().unwrap().as_mut().poll(cx);
+            if poll.is_ready() {
+                this.next = None;
+            }
+            poll
         }
     }
-    impl<Dyn: ?Sized> DerefMut for Buffered<'_, Dyn> {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            unsafe { self.0.as_mut() }
+
+    pub async fn test_async_iter() {
+        struct Countdown(u32);
+        impl AsyncIterator for Countdown {
+            type Item = u32;
+            async fn next(&mut self) -> Option<Self::Item> {
+                async {}.await;
+                self.0 = self.0.checked_sub(1)?;
+                Some(self.0)
+            }
+        }
+        struct Chunks(Vec<u8>);
+        impl AsyncIterator for Chunks {
+            type Item = u8;
+            async fn next(&mut self) -> Option<Self::Item> {
+                // Use the large buffer across await point to make the future large.
+                let large = [0u8; 128];
+                async {}.await;
+                Some(self.0.pop()? + large[0])
+            }
         }
+
+        let mut stack = std::pin::pin!([0u8; 64]);
+        let iter: &mut dyn DynAsyncIterator<Item = u32> = &mut Countdown(3);
+        let mut items = vec![];
+        while let Some(item) = iter.next().buffered(stack.as_mut()).await {
+            items.push(item);
+        }
+        assert_eq!(items, [2, 1, 0]);
+
+        async fn collect<T>(mut stream: Pin<&mut impl Stream<Item = T>>) -> Vec<T> {
+            let mut items = vec![];
+            while let Some(item) = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                items.push(item);
+            }
+            items
+        }
+        let iter: &mut dyn DynAsyncIterator<Item = u32> = &mut Countdown(3);
+        assert!(fits_in_buffer(iter.max_future_layout(), 64));
+        let stream = std::pin::pin!(InlineStream::<_, 64>::new(iter).unwrap());
+        assert_eq!(collect(stream).await, [2, 1, 0]);
+
+        // a buffer too small for the `next()` future is rejected up front,
+        // rather than boxing every item
+        let iter: &mut dyn DynAsyncIterator<Item = u8> = &mut Chunks(vec![1, 2]);
+        let layout = iter.next_layout();
+        let stream = InlineStream::<_, 64>::new(iter);
+        assert_eq!(stream.err(), Some(HeapFallback { layout }));
+        let iter: &mut dyn DynAsyncIterator<Item = u8> = &mut Chunks(vec![1, 2]);
+        let stream = std::pin::pin!(InlineStream::<_, 256>::new(iter).unwrap());
+        assert_eq!(collect(stream).await, [2, 1]);
+        println!("test_async_iter pass");
     }
+}
+pub use async_iter::*;
 
-    // normal buffer
-    unsafe impl<'a, Dyn: ?Sized> Container<Dyn> for &'a mut [u8] {
-        type Ptr = Buffered<'a, Dyn>;
-        type Err<Args> = Constructor<Dyn, Args>;
+// =========== Erase async traits with supertraits ===========
+mod supertrait {
+    use std::fmt::Debug;
 
-        fn try_init<Args>(
-            self,
-            constructor: Constructor<Dyn, Args>,
-        ) -> Result<Self::Ptr, Self::Err<Args>> {
-            let layout = constructor.layout();
-            let capacity = self.len();
+    pub use super::*;
 
-            let buf = self as *mut [u8] as *mut u8;
-            let buf_end = buf.wrapping_add(capacity);
-            let slot = buf.wrapping_add(buf.align_offset(layout.align()));
-            let slot_end = slot.wrapping_add(layout.size());
+    pub trait Named {
+        fn name(&self) -> &str;
+    }
 
-            if slot_end > buf_end || buf < slot {
-                return Err(constructor);
-            }
+    #[allow(async_fn_in_trait)]
+    pub trait Reader: Named {
+        async fn read(&mut self, buf: &mut [u8]) -> usize;
+    }
+
+    #[allow(async_fn_in_trait)]
+    pub trait Writer: Reader + Debug {
+        async fn write(&mut self, data: &[u8]) -> usize;
+    }
+
+    // A dyn trait keeps sync supertraits as they are, and replaces async ones
+    // with their dyn counterparts. Thus `dyn DynWriter` can be upcast to
+    // `dyn DynReader`, `dyn Named` or `dyn Debug`.
+    pub trait DynReader: Named {
+        fn read<'a>(
+            &'a mut self,
+            buf: &'a mut [u8],
+        ) -> PinConstructor<dyn 'a + Future<Output = usize>, (VoidPtr, &'a mut [u8])>;
+
+        fn read_layout(&self) -> Layout;
+
+        /// The layout fitting futures of all methods, including the ones of
+        /// supertraits.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.read_layout()])
+        }
+    }
+
+    pub trait DynWriter: DynReader + Debug {
+        fn write<'a>(
+            &'a mut self,
+            data: &'a [u8],
+        ) -> PinConstructor<dyn 'a + Future<Output = usize>, (VoidPtr, &'a [u8])>;
+
+        fn write_layout(&self) -> Layout;
+
+        /// The layout fitting futures of all methods, including the ones of
+        /// supertraits.
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.read_layout(), self.write_layout()])
+        }
+    }
+
+    impl<T: Reader + Sized> DynReader for T {
+        fn read<'a>(
+            &'a mut self,
+            buf: &'a mut [u8],
+        ) -> PinConstructor<dyn 'a + Future<Output = usize>, (VoidPtr, &'a mut [u8])> {
             unsafe {
-                let ptr = constructor.emplace(NonNull::new_unchecked(slot).cast());
-                Ok(Buffered(ptr, PhantomData))
+                Constructor::new(
+                    self.read_layout(),
+                    (NonNull::from(self).cast(), buf),
+                    |slot, (this, buf)| {
+                        let fun = <Self as Reader>::read;
+                        let slot = return_type_cast_ptr(&fun, slot);
+                        slot.write(fun(this.cast().as_mut(), buf));
+                        Emplaced::new(slot)
+                    },
+                )
             }
+            .pinned()
+        }
+
+        fn read_layout(&self) -> Layout {
+            return_type_layout(&<Self as Reader>::read)
         }
     }
 
-    // pinned buffer
-    unsafe impl<'a, Dyn: ?Sized> Container<Dyn> for Pin<&'a mut [u8]> {
-        type Ptr = Pin<Buffered<'a, Dyn>>;
-        type Err<Args> = Constructor<Dyn, Args>;
+    impl<T: Writer + Sized> DynWriter for T {
+        fn write<'a>(
+            &'a mut self,
+            data: &'a [u8],
+        ) -> PinConstructor<dyn 'a + Future<Output = usize>, (VoidPtr, &'a [u8])> {
+            unsafe {
+                Constructor::new(
+                    self.write_layout(),
+                    (NonNull::from(self).cast(), data),
+                    |slot, (this, data)| {
+                        let fun = <Self as Writer>::write;
+                        let slot = return_type_cast_ptr(&fun, slot);
+                        slot.write(fun(this.cast().as_mut(), data));
+                        Emplaced::new(slot)
+                    },
+                )
+            }
+            .pinned()
+        }
+
+        fn write_layout(&self) -> Layout {
+            return_type_layout(&<Self as Writer>::write)
+        }
+    }
 
-        fn try_init<Args>(
-            self,
-            constructor: Constructor<Dyn, Args>,
-        ) -> Result<Self::Ptr, Self::Err<Args>> {
-            self.get_mut()
-                .try_init(constructor)
-                .map(|ptr| unsafe { Pin::new_unchecked(ptr) })
+    pub async fn test_supertrait() {
+        #[derive(Debug)]
+        struct Pipe(Vec<u8>);
+        impl Named for Pipe {
+            fn name(&self) -> &str {
+                "pipe"
+            }
+        }
+        impl Reader for Pipe {
+            async fn read(&mut self, buf: &mut [u8]) -> usize {
+                let len = buf.len().min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0.drain(..len);
+                len
+            }
         }
+        impl Writer for Pipe {
+            async fn write(&mut self, data: &[u8]) -> usize {
+                self.0.extend_from_slice(data);
+                data.len()
+            }
+        }
+        assert_future_fits!(Pipe: Reader::read, 64);
+        assert_future_fits!(Pipe: Writer::write, 64);
+
+        let mut stack = std::pin::pin!([0u8; 64]);
+        let mut pipe = Pipe(vec![]);
+        let writer: &mut dyn DynWriter = &mut pipe;
+        assert_eq!(writer.write(b"yay").buffered(stack.as_mut()).await, 3);
+        assert_eq!(writer.name(), "pipe");
+        assert_eq!(format!("{writer:?}"), r#"Pipe([121, 97, 121])"#);
+
+        // async supertrait methods are callable both before and after upcasting
+        let mut buf = [0u8; 2];
+        assert_eq!(writer.read(&mut buf).buffered(stack.as_mut()).await, 2);
+        let reader: &mut dyn DynReader = writer;
+        assert_eq!(reader.read(&mut buf).buffered(stack.as_mut()).await, 1);
+        assert_eq!(buf, *b"ya");
+
+        let named: &dyn Named = reader;
+        assert_eq!(named.name(), "pipe");
+
+        // one scratch buffer sized for every method of the object
+        let writer: &mut dyn DynWriter = &mut pipe;
+        let layout = DynWriter::max_future_layout(writer);
+        assert!(layout.size() >= DynReader::max_future_layout(writer).size());
+        let mut scratch = vec![0u8; layout.size() + layout.align() - 1];
+        writer.write(b"!").buffered(Pin::new(&mut scratch)).await;
+        let fut = writer.read(&mut buf).buffered(Pin::new(&mut scratch));
+        assert_eq!(fut.await, 1);
+        println!("test_supertrait pass");
     }
 }
-pub use dyn_init::*;
+pub use supertrait::*;
 
 // =========== 例子 ===========
 mod example {
     pub use super::*;
+    pub use std::any::Any;
 
     pub trait Async {
         type Item;
@@ -282,25 +1072,31 @@ mod example {
     pub trait DynAsync {
         type Item;
 
-        fn foo(
-            &mut self,
+        fn foo<'a>(
+            &'a mut self,
             arg: String,
-        ) -> PinConstructor<dyn Future<Output = Self::Item>, (VoidPtr, String)>;
+        ) -> PinConstructor<dyn 'a + Future<Output = Self::Item>, (VoidPtr, String)>;
+
+        fn foo_layout(&self) -> Layout;
+
+        fn max_future_layout(&self) -> Layout {
+            max_layout(&[self.foo_layout()])
+        }
     }
 
     impl<T: Async + Sized> DynAsync for T {
         type Item = T::Item;
 
-        fn foo(
-            &mut self,
+        fn foo<'a>(
+            &'a mut self,
             arg: String,
-        ) -> PinConstructor<dyn Future<Output = Self::Item>, (VoidPtr, String)>
+        ) -> PinConstructor<dyn 'a + Future<Output = Self::Item>, (VoidPtr, String)>
         where
             Self: Sized,
         {
             unsafe {
                 Constructor::new(
-                    return_type_layout(&<Self as Async>::foo),
+                    self.foo_layout(),
                     (NonNull::from(self).cast(), arg),
                     |slot, (this, arg)| {
                         let fun = <Self as Async>::foo;
@@ -309,15 +1105,150 @@ mod example {
                         unsafe {
                             let out = fun(this.cast().as_mut(), arg);
                             slot.write(out);
-                            let ptr = slot.as_ptr() as *mut dyn Future<Output = T::Item>;
-                            NonNull::new_unchecked(ptr)
+                            Emplaced::new(slot)
                         }
                     },
                 )
             }
             .pinned()
         }
+
+        fn foo_layout(&self) -> Layout {
+            return_type_layout(&<Self as Async>::foo)
+        }
     }
+
+    /// A [`DynAsync`] that can be downcast to its implementor, which hot call
+    /// sites take to opt into [`devirtualize!`]. It's implemented for all
+    /// `'static` implementors, and upcasts to `dyn DynAsync`.
+    pub trait AnyAsync: DynAsync {
+        fn as_any_mut(&mut self) -> &mut dyn Any;
+    }
+
+    impl<T: DynAsync + Any> AnyAsync for T {
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    /// Tries to downcast `$imp` to each of the listed types and evaluates `$fast` with
+    /// the concrete implementor, so the call is static and its future lives inline in
+    /// the caller's state machine. Falls back to `$slow` with the original `dyn` object.
+    macro_rules! devirtualize {
+        (
+            match $imp:ident {
+                $($ty:ty)|+ => |$this:ident| $fast:expr,
+                _ => |$dyn_this:ident| $slow:expr $(,)?
+            }
+        ) => {{
+            let imp: &mut dyn AnyAsync<Item = _> = $imp;
+            'devirt: {
+                $(
+                    if let Some($this) = imp.as_any_mut().downcast_mut::<$ty>() {
+                        break 'devirt $fast;
+                    }
+                )+
+                let $dyn_this = imp;
+                $slow
+            }
+        }};
+    }
+    pub(crate) use devirtualize;
+
+    /// Generates a closed-world `enum` over the listed implementors of a trait,
+    /// along with an `enum` over their futures, for the given async methods.
+    /// Calls on it need neither a vtable nor a buffer, and as it also implements
+    /// the trait, it coerces to the dyn counterpart so that call sites like
+    /// `imp.foo(arg).await` are the same on both paths.
+    ///
+    /// At most 16 implementors are supported.
+    macro_rules! closed_async {
+        (@future $vis:vis $Future:ident [$(($V:ident $F:ident))*] [$W:ident $($Ws:ident)*] [$G:ident $($Gs:ident)*]) => {
+            closed_async!(@future $vis $Future [$(($V $F))* ($W $G)] [$($Ws)*] [$($Gs)*]);
+        };
+        (@future $vis:vis $Future:ident [$(($V:ident $F:ident))+] [] [$($_:ident)*]) => {
+            $vis enum $Future<$($F),+> {
+                $($V($F),)+
+            }
+
+            impl<Output, $($F: Future<Output = Output>),+> Future for $Future<$($F),+> {
+                type Output = Output;
+
+                fn poll(
+                    self: Pin<&mut Self>,
+                    cx: &mut std::task::Context<'_>,
+                ) -> std::task::Poll<Output> {
+                    // SAFETY: the variant is never moved out of the pinned enum.
+                    unsafe {
+                        match self.get_unchecked_mut() {
+                            $(Self::$V(fut) => Pin::new_unchecked(fut).poll(cx),)+
+                        }
+                    }
+                }
+            }
+        };
+        (@match $this:ident, $imp:ident, $Trait:ident::$m:ident $call:tt, $Future:ident, [$($V:ident)+]) => {
+            match $this {
+                $(Self::$V($imp) => $Future::$V(<$V as $Trait>::$m $call),)+
+            }
+        };
+        (@inherent $Trait:ident, $Future:ident, $variants:tt, $m:ident(&mut self $(, $arg:ident: $Arg:ty)* $(,)?) -> $Ret:ty) => {
+            pub fn $m(&mut self $(, $arg: $Arg)*) -> impl Future<Output = $Ret> {
+                closed_async!(@match self, imp, $Trait::$m(imp $(, $arg)*), $Future, $variants)
+            }
+        };
+        (@inherent $Trait:ident, $Future:ident, $variants:tt, $m:ident(&self $(, $arg:ident: $Arg:ty)* $(,)?) -> $Ret:ty) => {
+            pub fn $m(&self $(, $arg: $Arg)*) -> impl Future<Output = $Ret> {
+                closed_async!(@match self, imp, $Trait::$m(imp $(, $arg)*), $Future, $variants)
+            }
+        };
+        (@delegate $Name:ident, $m:ident(&mut self $(, $arg:ident: $Arg:ty)* $(,)?) -> $Ret:ty) => {
+            fn $m(&mut self $(, $arg: $Arg)*) -> impl Future<Output = $Ret> {
+                $Name::$m(self $(, $arg)*)
+            }
+        };
+        (@delegate $Name:ident, $m:ident(&self $(, $arg:ident: $Arg:ty)* $(,)?) -> $Ret:ty) => {
+            fn $m(&self $(, $arg: $Arg)*) -> impl Future<Output = $Ret> {
+                $Name::$m(self $(, $arg)*)
+            }
+        };
+        (@impl $Name:ident: $Trait:ident [$($Assoc:ident = $AssocTy:ty),*], $Future:ident, $variants:tt,
+            $(async fn $m:ident $sig:tt -> $Ret:ty;)+
+        ) => {
+            impl $Name {
+                $(closed_async!(@inherent $Trait, $Future, $variants, $m $sig -> $Ret);)+
+            }
+
+            impl $Trait for $Name {
+                $(type $Assoc = $AssocTy;)*
+
+                $(closed_async!(@delegate $Name, $m $sig -> $Ret);)+
+            }
+        };
+        (
+            $vis:vis enum $Name:ident: $Trait:ident $(<$($Assoc:ident = $AssocTy:ty),+ $(,)?>)? {
+                $($V:ident),+ $(,)?
+            }
+            future $Future:ident;
+            $($method:tt)+
+        ) => {
+            $vis enum $Name {
+                $($V($V),)+
+            }
+
+            $(
+                impl From<$V> for $Name {
+                    fn from(imp: $V) -> Self {
+                        Self::$V(imp)
+                    }
+                }
+            )+
+
+            closed_async!(@future $vis $Future [] [$($V)+] [F0 F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12 F13 F14 F15]);
+            closed_async!(@impl $Name: $Trait [$($($Assoc = $AssocTy),+)?], $Future, [$($V)+], $($method)+);
+        };
+    }
+    pub(crate) use closed_async;
 }
 use example::*;
 
@@ -336,24 +1267,159 @@ async fn dynamic_dispatch<Item: Eq + std::fmt::Debug>(
         Ok(fut) => fut.await,
         Err(c) => c.boxed().await,
     };
-    let b = match imp.foo(arg).try_buffered(stack.as_mut()) {
+    let b = match imp.foo(arg.clone()).try_buffered(stack.as_mut()) {
         Ok(fut) => fut.await,
         Err(c) => c.boxed().await,
     };
     assert_eq!(a, b);
+
+    // place the future inline or on the heap, just like the above
+    let c = imp.foo(arg.clone()).await;
+    assert_eq!(a, c);
+    let d = dyn_await!(imp.foo(arg), stack = 64);
+    assert_eq!(a, d);
     a
 }
 
-async fn run() {
-    test_return_type_layout();
+/// A hot call site where most calls go to `AppendYay`.
+async fn hot_dispatch(imp: &mut dyn AnyAsync<Item = String>, arg: String) -> (String, bool) {
+    devirtualize!(match imp {
+        AppendYay => |imp| (Async::foo(imp, arg).await, true),
+        _ => |imp| (dyn_await!(imp.foo(arg), stack = 64), false),
+    })
+}
+
+struct AppendYay;
+impl Async for AppendYay {
+    type Item = String;
+    async fn foo(&mut self, arg: String) -> Self::Item {
+        arg + ", yay!"
+    }
+}
+
+struct AppendNay;
+impl Async for AppendNay {
+    type Item = String;
+    async fn foo(&mut self, arg: String) -> Self::Item {
+        arg + ", nay!"
+    }
+}
+
+async fn test_devirtualize() {
+    let out = hot_dispatch(&mut AppendYay, "foo".to_owned()).await;
+    assert_eq!(out, ("foo, yay!".to_owned(), true));
+    let out = hot_dispatch(&mut AppendNay, "foo".to_owned()).await;
+    assert_eq!(out, ("foo, nay!".to_owned(), false));
+
+    println!("test_devirtualize pass");
+}
+
+closed_async! {
+    enum ClosedYay: Async<Item = String> {
+        AppendYay,
+        AppendNay,
+    }
+    future ClosedYayFuture;
+    async fn foo(&mut self, arg: String) -> String;
+}
+
+async fn test_closed_async() {
+    let mut imps = [ClosedYay::from(AppendYay), ClosedYay::from(AppendNay)];
+    let [yay, nay] = &mut imps;
+    assert_eq!(yay.foo("foo".to_owned()).await, "foo, yay!");
+    assert_eq!(nay.foo("foo".to_owned()).await, "foo, nay!");
+
+    // Switching back to the dyn path leaves the call site untouched.
+    let imp: &mut dyn DynAsync<Item = String> = nay;
+    assert_eq!(imp.foo("foo".to_owned()).await, "foo, nay!");
+    let item = dynamic_dispatch(yay, "foo".to_owned()).await;
+    assert_eq!(item, "foo, yay!");
+
+    // any trait and methods, as long as the receiver is `&self` or `&mut self`
+    trait Greet {
+        async fn greet(&self, name: &str) -> String;
+        async fn rename(&mut self, to: &'static str, excited: bool);
+    }
+    struct Hello(&'static str);
+    struct Bye;
+    impl Greet for Hello {
+        async fn greet(&self, name: &str) -> String {
+            format!("{} {name}", self.0)
+        }
+        async fn rename(&mut self, to: &'static str, _: bool) {
+            self.0 = to;
+        }
+    }
+    impl Greet for Bye {
+        async fn greet(&self, name: &str) -> String {
+            format!("bye {name}")
+        }
+        async fn rename(&mut self, _: &'static str, _: bool) {}
+    }
+    closed_async! {
+        enum ClosedGreet: Greet {
+            Hello,
+            Bye,
+        }
+        future ClosedGreetFuture;
+        async fn greet(&self, name: &str) -> String;
+        async fn rename(&mut self, to: &'static str, excited: bool) -> ();
+    }
+    let mut hello = ClosedGreet::from(Hello("hello"));
+    assert_eq!(hello.greet("foo").await, "hello foo");
+    hello.rename("hi", true).await;
+    assert_eq!(Greet::greet(&hello, "foo").await, "hi foo");
+    assert_eq!(ClosedGreet::from(Bye).greet("foo").await, "bye foo");
 
-    struct AppendYay;
-    impl Async for AppendYay {
+    println!("test_closed_async pass");
+}
+
+async fn test_no_heap() {
+    struct Large;
+    impl Async for Large {
         type Item = String;
         async fn foo(&mut self, arg: String) -> Self::Item {
-            arg + ", yay!"
+            let buf = [0u8; 128];
+            async {}.await;
+            std::hint::black_box(&buf);
+            arg
         }
     }
+
+    let imp: &mut dyn DynAsync<Item = String> = &mut AppendYay;
+    let item = try_dyn_await!(imp.foo("foo".to_owned()), stack = 64);
+    assert_eq!(item, Ok("foo, yay!".to_owned()));
+    assert_eq!(imp.foo("foo".to_owned()).no_heap().await, item);
+
+    // compile fail:
+    //
+    // imp.foo("foo".to_owned()).no_heap().boxed();
+    // assert_future_fits!(Large: Async::foo, 64);
+    assert_future_fits!(AppendYay: Async::foo, 64);
+
+    let imp: &mut dyn DynAsync<Item = String> = &mut Large;
+    let err = Err(HeapFallback {
+        layout: imp.foo_layout(),
+    });
+    assert_eq!(try_dyn_await!(imp.foo("foo".to_owned()), stack = 64), err);
+    assert_eq!(imp.foo("foo".to_owned()).no_heap().await, err);
+    let item = imp.foo("foo".to_owned()).no_heap().inline::<256>().await;
+    assert_eq!(item, Ok("foo".to_owned()));
+
+    println!("test_no_heap pass");
+}
+
+async fn run() {
+    test_dyn_async_fn().await;
+    test_combinator().await;
+    test_retry().await;
+    test_call_queue().await;
+    test_async_iter().await;
+    test_supertrait().await;
+    test_devirtualize().await;
+    test_closed_async().await;
+    test_no_heap().await;
+
     struct PrintYay;
     impl Async for PrintYay {
         type Item = ();
@@ -370,9 +1436,18 @@ async fn run() {
         }
     }
 
+    // These futures always fit in the stack of `dynamic_dispatch`. Compile fail:
+    //
+    // assert_future_fits!(AppendYay: Async::foo, 8);
+    assert_future_fits!(AppendYay: Async::foo, 64);
+    assert_future_fits!(PrintYay: Async::foo, 64);
+    assert_future_fits!(CheckYay<'static>: Async::foo, 64);
+
     dynamic_dispatch(&mut PrintYay, "foo".to_owned()).await;
 
     let item = dynamic_dispatch(&mut AppendYay, "foo".to_owned()).await;
+    let imp: &mut dyn DynAsync<Item = String> = &mut AppendYay;
+    assert_eq!(imp.max_future_layout(), imp.foo(item.clone()).layout());
     let item = dynamic_dispatch(&mut CheckYay(&item), "foo".to_owned()).await;
     assert_eq!(item, "foo, yay!");
 
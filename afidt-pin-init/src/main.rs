@@ -1,5 +1,6 @@
 #![feature(ptr_metadata)]
 
+use afidt::HeapFallback;
 use pin_init::{DynInPlaceInit, dyn_init};
 use std::{
     pin::{Pin, pin},
@@ -42,19 +43,27 @@ async fn run() {
     a.foo().await;
 
     let ref_a: &dyn Async = &a;
-    dynamic_dispatch(ref_a).await;
+    dynamic_dispatch(ref_a, false).await.unwrap();
 
-    dynamic_dispatch(&B).await;
+    dynamic_dispatch(&B, false).await.unwrap();
+    if let Err(e) = dynamic_dispatch(&B, true).await {
+        println!("{e:?}");
+    }
 }
 
 const FUT_STACK_SIZE: usize = 64;
 
-async fn dynamic_dispatch(ref_a: &dyn Async) {
+/// Fails with a `HeapFallback` rather than allocating if `no_heap` is set
+/// and the future doesn't fit on the stack.
+async fn dynamic_dispatch(ref_a: &dyn Async, no_heap: bool) -> Result<(), HeapFallback> {
     let dyn_foo = ref_a.dyn_foo();
     let layout = dyn_foo.layout();
     let fut_size = layout.size();
 
     if fut_size > FUT_STACK_SIZE {
+        if no_heap {
+            return Err(HeapFallback { layout });
+        }
         println!("Heap allocation as the future is too large.");
         Box::into_pin(Box::dyn_init(dyn_foo)).await;
     } else {
@@ -68,9 +77,12 @@ async fn dynamic_dispatch(ref_a: &dyn Async) {
 
         // dbg!(start, slot, slot_end, end, fut_size, layout.align());
         if !(start <= slot && slot_end <= end) {
+            if no_heap {
+                return Err(HeapFallback { layout });
+            }
             println!("Heap allocation due to stack is not enough.");
             Box::into_pin(Box::dyn_init(dyn_foo)).await;
-            return;
+            return Ok(());
         }
 
         unsafe {
@@ -87,6 +99,7 @@ async fn dynamic_dispatch(ref_a: &dyn Async) {
             ptr::drop_in_place(ptr_dyn_fut);
         }
     }
+    Ok(())
 }
 
 // [OUTPUT]
@@ -95,3 +108,4 @@ async fn dynamic_dispatch(ref_a: &dyn Async) {
 // foo!
 // Heap allocation as the future is too large.
 // B
+// HeapFallback { layout: Layout { size: .., align: .. } }
@@ -0,0 +1,80 @@
+//! Places erased futures with the library, in a buffer and on the heap.
+#![allow(clippy::missing_safety_doc)]
+
+use std::future::Future;
+use std::pin::pin;
+use std::ptr::NonNull;
+
+use afidt::*;
+
+trait Async {
+    async fn foo(&mut self, arg: u32) -> u32;
+}
+
+trait DynAsync {
+    fn foo(&mut self, arg: u32) -> PinConstructor<dyn '_ + Future<Output = u32>, (VoidPtr, u32)>;
+}
+
+impl<T: Async> DynAsync for T {
+    fn foo(&mut self, arg: u32) -> PinConstructor<dyn '_ + Future<Output = u32>, (VoidPtr, u32)> {
+        let layout = return_type_layout(&<T as Async>::foo);
+        unsafe {
+            Constructor::new(
+                layout,
+                (NonNull::from(self).cast(), arg),
+                |slot, (this, arg)| {
+                    let fun = <T as Async>::foo;
+                    let slot = return_type_cast_ptr(&fun, slot);
+                    slot.write(fun(this.cast().as_mut(), arg));
+                    Emplaced::new(slot)
+                },
+            )
+        }
+        .pinned()
+    }
+}
+
+struct AddOne;
+impl Async for AddOne {
+    async fn foo(&mut self, arg: u32) -> u32 {
+        arg + 1
+    }
+}
+
+assert_future_fits!(AddOne: Async::foo, 32);
+
+async fn test_constructor() {
+    let imp: &mut dyn DynAsync = &mut AddOne;
+    let mut stack = pin!([0u8; 32]);
+    assert_eq!(imp.foo(1).buffered(stack.as_mut()).await, 2);
+    assert_eq!(imp.foo(2).boxed().await, 3);
+
+    let mut tiny = pin!([0u8; 1]);
+    assert!(imp.foo(3).try_buffered(tiny.as_mut()).is_err());
+    println!("test_constructor pass");
+}
+
+async fn test_dyn_object() {
+    let mut imp = AddOne;
+    let init = unsafe {
+        DynInit::<dyn Future<Output = u32>, _>::new(
+            (NonNull::from(&mut imp).cast::<Void>(), 7),
+            || return_type_layout(&<AddOne as Async>::foo),
+            |slot, (this, arg)| {
+                let fun = <AddOne as Async>::foo;
+                return_type_cast_ptr(&fun, slot).write(fun(this.cast().as_mut(), arg));
+                return_type_object::<_, _, dyn Future<Output = u32>>(&fun, slot)
+            },
+        )
+    };
+    let mut obj = DynBox::init(init);
+    assert_eq!(obj.as_pin_mut().await, 8);
+    println!("test_dyn_object pass");
+}
+
+fn main() {
+    pollster::block_on(async {
+        test_constructor().await;
+        test_dyn_object().await;
+    })
+}
@@ -0,0 +1,144 @@
+//! The representation of constructed `dyn` objects, which is a full `dyn`
+//! pointer on stable, or only its metadata with the `nightly` feature, from
+//! which `ptr::from_raw_parts_mut` rebuilds the pointer.
+
+use core::fmt;
+use core::ptr::NonNull;
+
+use crate::VoidPtr;
+
+/// Implemented by `dyn` types for the concrete types that unsize to them,
+/// so that [`Emplaced`] erases `T` without the caller building the `dyn`
+/// pointer.
+pub unsafe trait UnsizeFrom<T> {
+    fn unsize(ptr: NonNull<T>) -> NonNull<Self>;
+}
+
+unsafe impl<'a, Fut: 'a + Future> UnsizeFrom<Fut> for dyn 'a + Future<Output = Fut::Output> {
+    fn unsize(ptr: NonNull<Fut>) -> NonNull<Self> {
+        ptr
+    }
+}
+unsafe impl<'a, Fut: 'a + Future + Send> UnsizeFrom<Fut>
+    for dyn 'a + Future<Output = Fut::Output> + Send
+{
+    fn unsize(ptr: NonNull<Fut>) -> NonNull<Self> {
+        ptr
+    }
+}
+unsafe impl<'a, T: 'a + fmt::Debug> UnsizeFrom<T> for dyn 'a + fmt::Debug {
+    fn unsize(ptr: NonNull<T>) -> NonNull<Self> {
+        ptr
+    }
+}
+unsafe impl<'a, T: 'a + Send> UnsizeFrom<T> for dyn 'a + Send {
+    fn unsize(ptr: NonNull<T>) -> NonNull<Self> {
+        ptr
+    }
+}
+
+/// A `dyn` object that has been constructed in a slot, which is rebuilt
+/// from the address of the slot.
+///
+/// [`Constructor`] erases its objects through it. So do the simulated
+/// objects, e.g. [`DynFuture`], with the `nightly` feature, where they call
+/// through the rebuilt `dyn` pointer instead of their manual vtables.
+///
+/// [`Constructor`]: crate::Constructor
+/// [`DynFuture`]: crate::DynFuture
+pub struct Emplaced<Dyn: ?Sized>(Repr<Dyn>);
+
+#[cfg(not(feature = "nightly"))]
+type Repr<Dyn> = NonNull<Dyn>;
+
+#[cfg(feature = "nightly")]
+type Repr<Dyn> = <Dyn as core::ptr::Pointee>::Metadata;
+
+impl<Dyn: ?Sized> Clone for Emplaced<Dyn> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<Dyn: ?Sized> Copy for Emplaced<Dyn> {}
+
+impl<Dyn: ?Sized> Emplaced<Dyn> {
+    /// Erases the `T` in `slot` as `Dyn`.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be the slot passed to the initializer, and the object must
+    /// have been constructed in it.
+    pub unsafe fn new<T>(slot: NonNull<T>) -> Self
+    where
+        Dyn: UnsizeFrom<T>,
+    {
+        #[cfg(not(feature = "nightly"))]
+        return Self(Dyn::unsize(slot));
+        #[cfg(feature = "nightly")]
+        return Self(core::ptr::metadata(Dyn::unsize(slot).as_ptr()));
+    }
+
+    /// Like [`new`](Self::new), for objects that aren't unsized from a
+    /// concrete type, e.g. those ending with another `dyn` object.
+    pub(crate) unsafe fn from_ptr(ptr: NonNull<Dyn>) -> Self {
+        #[cfg(not(feature = "nightly"))]
+        return Self(ptr);
+        #[cfg(feature = "nightly")]
+        return Self(ptr.to_raw_parts().1);
+    }
+
+    /// Changes the lifetime of the object, which the simulated objects don't
+    /// track.
+    ///
+    /// # Safety
+    ///
+    /// `Other` must be `Dyn` with another lifetime.
+    #[cfg(feature = "nightly")]
+    pub(crate) unsafe fn transmute<Other: ?Sized>(self) -> Emplaced<Other> {
+        core::mem::transmute_copy(&self)
+    }
+
+    /// Rebuilds the `dyn` pointer to the object.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be the slot the object was constructed in.
+    pub(crate) unsafe fn into_ptr(self, slot: VoidPtr) -> NonNull<Dyn> {
+        #[cfg(not(feature = "nightly"))]
+        {
+            debug_assert_eq!(self.0.cast::<u8>(), slot.cast::<u8>());
+            self.0
+        }
+        #[cfg(feature = "nightly")]
+        {
+            NonNull::new_unchecked(core::ptr::from_raw_parts_mut(slot.as_ptr(), self.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+
+    use super::*;
+    use crate::Constructor;
+
+    #[test]
+    fn unsize() {
+        let debug = unsafe {
+            Constructor::<dyn fmt::Debug, _>::new(
+                Layout::new::<[u8; 3]>(),
+                [1, 2, 3],
+                |slot, val| {
+                    let slot = slot.cast::<[u8; 3]>();
+                    slot.write(val);
+                    Emplaced::new(slot)
+                },
+            )
+        };
+        let mut buf = [0u8; 8];
+        let obj = debug.buffered(&mut buf);
+        assert_eq!(std::format!("{:?}", &*obj), "[1, 2, 3]");
+        assert_eq!(Layout::for_value(&*obj), Layout::new::<[u8; 3]>());
+    }
+}
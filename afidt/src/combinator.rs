@@ -0,0 +1,461 @@
+//! Compose constructors, whose objects are constructed in a single slot.
+
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll, ready};
+
+use crate::{Constructor, Emplaced, INLINE_LEN, PinConstructor, VoidPtr, fits_in_buffer};
+
+/// A sized state followed by the `dyn` object it drives, laid out like a
+/// `#[repr(C)]` struct so that both of them live in a single slot.
+///
+/// A state driving more than one object is followed by raw bytes instead,
+/// in which it constructs the objects.
+#[repr(C)]
+pub struct Composed<S, Dyn: ?Sized> {
+    state: S,
+    inner: Dyn,
+}
+
+/// The constructor of a [`Composed`] object, which constructs the inner
+/// object and then writes the state in front of it.
+pub type ComposedConstructor<S, Dyn, Args> =
+    Constructor<Composed<S, Dyn>, (S, Constructor<Dyn, Args>)>;
+pub type ComposedPinConstructor<S, Dyn, Args, const N: usize = INLINE_LEN> =
+    PinConstructor<Composed<S, Dyn>, (S, Constructor<Dyn, Args>), N>;
+
+pub type JoinConstructor<A, ArgsA, B, ArgsB> =
+    Constructor<Composed<Join<A, B>, [u8]>, (Constructor<A, ArgsA>, Constructor<B, ArgsB>)>;
+pub type JoinPinConstructor<A, ArgsA, B, ArgsB, const N: usize = INLINE_LEN> =
+    PinConstructor<Composed<Join<A, B>, [u8]>, (Constructor<A, ArgsA>, Constructor<B, ArgsB>), N>;
+
+pub type ThenConstructor<Dyn, Args, F, Next, NextArgs> =
+    Constructor<Composed<Then<Dyn, F, Next, NextArgs>, [u8]>, (Constructor<Dyn, Args>, F, Layout)>;
+pub type ThenPinConstructor<Dyn, Args, F, Next, NextArgs, const N: usize = INLINE_LEN> =
+    PinConstructor<
+        Composed<Then<Dyn, F, Next, NextArgs>, [u8]>,
+        (Constructor<Dyn, Args>, F, Layout),
+        N,
+    >;
+
+impl<S, Dyn: ?Sized> Composed<S, Dyn> {
+    pub(crate) fn project(self: Pin<&mut Self>) -> (Pin<&mut S>, Pin<&mut Dyn>) {
+        // SAFETY: both fields are structurally pinned.
+        unsafe {
+            let this = self.get_unchecked_mut();
+            (
+                Pin::new_unchecked(&mut this.state),
+                Pin::new_unchecked(&mut this.inner),
+            )
+        }
+    }
+}
+
+impl<S> Composed<S, [u8]> {
+    /// The layout of a state followed by `len` bytes.
+    fn layout(len: usize) -> Layout {
+        let (layout, _) = Layout::new::<S>()
+            .extend(Layout::array::<u8>(len).expect("layout overflow"))
+            .expect("layout overflow");
+        layout.pad_to_align()
+    }
+
+    /// Writes the state to `slot`, and points to it along with the `len`
+    /// bytes following it.
+    unsafe fn write(slot: VoidPtr, state: S, len: usize) -> NonNull<Self> {
+        unsafe {
+            slot.cast::<S>().write(state);
+            let ptr = core::ptr::slice_from_raw_parts_mut(slot.as_ptr() as *mut u8, len);
+            NonNull::new_unchecked(ptr as *mut Self)
+        }
+    }
+}
+
+/// The bytes following the state in `slot`.
+fn tail<S>(slot: VoidPtr) -> *mut u8 {
+    // `[u8]` is never padded, so the bytes start right after the state.
+    slot.as_ptr().cast::<u8>().wrapping_add(size_of::<S>())
+}
+
+/// Constructs the object at the first address in `bytes` aligned for it.
+///
+/// # Safety
+///
+/// `bytes` must be exclusive for this construction, and span at least
+/// [`reserved`] bytes for the layout of the object.
+unsafe fn emplace_aligned<Dyn: ?Sized, Args>(
+    bytes: *mut u8,
+    constructor: Constructor<Dyn, Args>,
+) -> NonNull<Dyn> {
+    unsafe {
+        let slot = bytes.add(bytes.align_offset(constructor.layout().align()));
+        constructor.emplace(NonNull::new_unchecked(slot).cast())
+    }
+}
+
+/// The number of bytes fitting objects of `layout` at any address.
+const fn reserved(layout: Layout) -> usize {
+    layout.size() + (layout.align() - 1)
+}
+
+impl<Dyn: ?Sized, Args> Constructor<Dyn, Args> {
+    pub(crate) fn compose<S>(self, state: S) -> ComposedConstructor<S, Dyn, Args> {
+        let (layout, _) = Layout::new::<S>()
+            .extend(self.layout())
+            .expect("layout overflow");
+        unsafe {
+            Constructor::new(
+                layout.pad_to_align(),
+                (state, self),
+                |slot, (state, inner)| {
+                    let (_, offset) = Layout::new::<S>().extend(inner.layout()).unwrap();
+                    // The `dyn` object determines the metadata of `Composed`.
+                    let ptr = inner.emplace(slot.byte_add(offset)).as_ptr();
+                    let ptr = (ptr as *mut Composed<S, Dyn>).byte_sub(offset);
+                    slot.cast::<S>().write(state);
+                    Emplaced::from_ptr(NonNull::new_unchecked(ptr))
+                },
+            )
+        }
+    }
+}
+
+impl<Dyn: ?Sized + Future, Args> Constructor<Dyn, Args> {
+    /// Maps the output of the future with `f`.
+    pub fn map<F, U>(self, f: F) -> ComposedConstructor<Map<F>, Dyn, Args>
+    where
+        F: FnOnce(Dyn::Output) -> U,
+    {
+        self.compose(Map { f: Some(f) })
+    }
+
+    /// Chains the future with the one constructed by `f`.
+    ///
+    /// The next future is constructed in place of this one once it
+    /// completes, so the slot reserves room for `next_layout` as well.
+    ///
+    /// # Panics
+    ///
+    /// Polling panics if the next future doesn't fit in `next_layout`.
+    pub fn then<F, Next, NextArgs>(
+        self,
+        next_layout: Layout,
+        f: F,
+    ) -> ThenConstructor<Dyn, Args, F, Next, NextArgs>
+    where
+        F: FnOnce(Dyn::Output) -> PinConstructor<Next, NextArgs>,
+        Next: ?Sized + Future,
+    {
+        let len = reserved(self.layout()).max(reserved(next_layout));
+        unsafe {
+            Constructor::new(
+                Composed::<Then<Dyn, F, Next, NextArgs>, [u8]>::layout(len),
+                (self, f, next_layout),
+                |slot, (first, f, next_layout)| {
+                    let len = reserved(first.layout()).max(reserved(next_layout));
+                    let first = emplace_aligned(tail::<Then<Dyn, F, Next, NextArgs>>(slot), first);
+                    let state = Then {
+                        f: Some(f),
+                        first: Some(first),
+                        next: None,
+                        _marker: PhantomData,
+                    };
+                    Emplaced::from_ptr(Composed::write(slot, state, len))
+                },
+            )
+        }
+    }
+
+    /// Runs the future along with `other` and waits for both of them.
+    ///
+    /// Both futures are constructed in the same slot.
+    pub fn join<B, ArgsB>(
+        self,
+        other: Constructor<B, ArgsB>,
+    ) -> JoinConstructor<Dyn, Args, B, ArgsB>
+    where
+        B: ?Sized + Future,
+    {
+        let len = reserved(self.layout()) + reserved(other.layout());
+        unsafe {
+            Constructor::new(
+                Composed::<Join<Dyn, B>, [u8]>::layout(len),
+                (self, other),
+                |slot, (a, b)| {
+                    let len = reserved(a.layout()) + reserved(b.layout());
+                    let size = a.layout().size();
+                    let a = emplace_aligned(tail::<Join<Dyn, B>>(slot), a);
+                    let b = emplace_aligned(a.as_ptr().cast::<u8>().add(size), b);
+                    let state = Join {
+                        a: Some(a),
+                        b: Some(b),
+                        a_out: None,
+                        b_out: None,
+                    };
+                    Emplaced::from_ptr(Composed::write(slot, state, len))
+                },
+            )
+        }
+    }
+
+    /// Fails with [`Elapsed`] if `deadline` completes before the future.
+    pub fn timeout<D>(self, deadline: D) -> ComposedConstructor<Timeout<D>, Dyn, Args>
+    where
+        D: Future<Output = ()>,
+    {
+        self.compose(Timeout { deadline })
+    }
+}
+
+/// The composed constructors keep the inline capacity of `self`.
+impl<Dyn: ?Sized + Future, Args, const N: usize> PinConstructor<Dyn, Args, N> {
+    pub fn map<F, U>(self, f: F) -> ComposedPinConstructor<Map<F>, Dyn, Args, N>
+    where
+        F: FnOnce(Dyn::Output) -> U,
+    {
+        self.unpinned().map(f).pinned().with_inline_len()
+    }
+
+    pub fn then<F, Next, NextArgs>(
+        self,
+        next_layout: Layout,
+        f: F,
+    ) -> ThenPinConstructor<Dyn, Args, F, Next, NextArgs, N>
+    where
+        F: FnOnce(Dyn::Output) -> PinConstructor<Next, NextArgs>,
+        Next: ?Sized + Future,
+    {
+        self.unpinned()
+            .then(next_layout, f)
+            .pinned()
+            .with_inline_len()
+    }
+
+    pub fn join<B, ArgsB, const M: usize>(
+        self,
+        other: PinConstructor<B, ArgsB, M>,
+    ) -> JoinPinConstructor<Dyn, Args, B, ArgsB, N>
+    where
+        B: ?Sized + Future,
+    {
+        self.unpinned()
+            .join(other.unpinned())
+            .pinned()
+            .with_inline_len()
+    }
+
+    pub fn timeout<D>(self, deadline: D) -> ComposedPinConstructor<Timeout<D>, Dyn, Args, N>
+    where
+        D: Future<Output = ()>,
+    {
+        self.unpinned().timeout(deadline).pinned().with_inline_len()
+    }
+}
+
+pub struct Map<F> {
+    f: Option<F>,
+}
+
+impl<F, U, Dyn> Future for Composed<Map<F>, Dyn>
+where
+    Dyn: ?Sized + Future,
+    F: FnOnce(Dyn::Output) -> U,
+{
+    type Output = U;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (state, inner) = self.project();
+        let out = ready!(inner.poll(cx));
+        // SAFETY: `f` is never pinned.
+        let f = unsafe { state.get_unchecked_mut() }.f.take();
+        Poll::Ready(f.expect("polled after completion")(out))
+    }
+}
+
+/// The state of [`then`](Constructor::then), which owns whichever of the
+/// futures is alive in the bytes following it.
+pub struct Then<Dyn: ?Sized, F, Next: ?Sized, NextArgs> {
+    f: Option<F>,
+    first: Option<NonNull<Dyn>>,
+    next: Option<NonNull<Next>>,
+    _marker: PhantomData<fn(NextArgs)>,
+}
+
+unsafe impl<Dyn, F, Next, NextArgs> Send for Then<Dyn, F, Next, NextArgs>
+where
+    Dyn: ?Sized + Send,
+    F: Send,
+    Next: ?Sized + Send,
+{
+}
+
+impl<Dyn: ?Sized, F, Next: ?Sized, NextArgs> Drop for Then<Dyn, F, Next, NextArgs> {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(first) = self.first.take() {
+                first.drop_in_place();
+            }
+            if let Some(next) = self.next.take() {
+                next.drop_in_place();
+            }
+        }
+    }
+}
+
+impl<Dyn, F, Next, NextArgs> Future for Composed<Then<Dyn, F, Next, NextArgs>, [u8]>
+where
+    Dyn: ?Sized + Future,
+    F: FnOnce(Dyn::Output) -> PinConstructor<Next, NextArgs>,
+    Next: ?Sized + Future,
+{
+    type Output = Next::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (state, bytes) = self.project();
+        // SAFETY: the futures live in the pinned bytes, and `f` is never
+        // pinned.
+        let state = unsafe { state.get_unchecked_mut() };
+        if let Some(first) = state.first {
+            let out = ready!(unsafe { Pin::new_unchecked(&mut *first.as_ptr()) }.poll(cx));
+            state.first = None;
+            unsafe { first.drop_in_place() };
+            let f = state.f.take().unwrap();
+            let next = f(out).unpinned();
+            let bytes = unsafe { bytes.get_unchecked_mut() };
+            assert!(
+                fits_in_buffer(next.layout(), bytes.len()),
+                "the next future doesn't fit in the reserved layout"
+            );
+            state.next = Some(unsafe { emplace_aligned(bytes.as_mut_ptr(), next) });
+        }
+        let next = state.next.expect("polled after completion");
+        let out = ready!(unsafe { Pin::new_unchecked(&mut *next.as_ptr()) }.poll(cx));
+        state.next = None;
+        unsafe { next.drop_in_place() };
+        Poll::Ready(out)
+    }
+}
+
+/// The state of [`join`](Constructor::join), which owns the futures
+/// constructed in the bytes following it until they complete.
+pub struct Join<A: ?Sized + Future, B: ?Sized + Future> {
+    a: Option<NonNull<A>>,
+    b: Option<NonNull<B>>,
+    a_out: Option<A::Output>,
+    b_out: Option<B::Output>,
+}
+
+unsafe impl<A, B> Send for Join<A, B>
+where
+    A: ?Sized + Future + Send,
+    B: ?Sized + Future + Send,
+    A::Output: Send,
+    B::Output: Send,
+{
+}
+
+impl<A: ?Sized + Future, B: ?Sized + Future> Drop for Join<A, B> {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(a) = self.a.take() {
+                a.drop_in_place();
+            }
+            if let Some(b) = self.b.take() {
+                b.drop_in_place();
+            }
+        }
+    }
+}
+
+impl<A, B> Future for Composed<Join<A, B>, [u8]>
+where
+    A: ?Sized + Future,
+    B: ?Sized + Future,
+{
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (state, _) = self.project();
+        // SAFETY: the futures live in the pinned bytes and are dropped in
+        // place once they complete, and the outputs are never pinned.
+        let state = unsafe { state.get_unchecked_mut() };
+        if let Some(a) = state.a
+            && let Poll::Ready(out) = unsafe { Pin::new_unchecked(&mut *a.as_ptr()) }.poll(cx)
+        {
+            state.a = None;
+            unsafe { a.drop_in_place() };
+            state.a_out = Some(out);
+        }
+        if let Some(b) = state.b
+            && let Poll::Ready(out) = unsafe { Pin::new_unchecked(&mut *b.as_ptr()) }.poll(cx)
+        {
+            state.b = None;
+            unsafe { b.drop_in_place() };
+            state.b_out = Some(out);
+        }
+        match (&state.a_out, &state.b_out) {
+            (Some(_), Some(_)) => {
+                Poll::Ready((state.a_out.take().unwrap(), state.b_out.take().unwrap()))
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+pub struct Timeout<D> {
+    deadline: D,
+}
+
+/// The error returned when the deadline of a [`timeout`] has elapsed.
+///
+/// [`timeout`]: Constructor::timeout
+#[derive(Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl<D, Dyn> Future for Composed<Timeout<D>, Dyn>
+where
+    Dyn: ?Sized + Future,
+    D: Future<Output = ()>,
+{
+    type Output = Result<Dyn::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (state, inner) = self.project();
+        if let Poll::Ready(out) = inner.poll(cx) {
+            return Poll::Ready(Ok(out));
+        }
+        // SAFETY: `deadline` is structurally pinned.
+        let deadline = unsafe { state.map_unchecked_mut(|s| &mut s.deadline) };
+        ready!(deadline.poll(cx));
+        Poll::Ready(Err(Elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::block_on;
+
+    use super::*;
+    use crate::test::{call, hold};
+
+    #[test]
+    fn single_slot() {
+        let mut buf = [0u8; 256];
+        let fut = call(hold::<16>)
+            .map(|n| n * 2)
+            .join(call(hold::<32>).timeout(core::future::pending()));
+        let layout = fut.layout();
+        let fut = fut.buffered(Pin::new(&mut buf[..]));
+        assert_eq!(Layout::for_value(&*fut), layout);
+        assert_eq!(block_on(fut), (32, Ok(32)));
+
+        let fut = call(hold::<16>).then(Layout::new::<[u8; 32]>(), |n| {
+            call(move || async move { n + 1 })
+        });
+        assert_eq!(block_on(fut.buffered(Pin::new(&mut buf[..]))), 17);
+        let fut = call(core::future::pending::<()>).timeout(async {});
+        assert_eq!(block_on(fut.buffered(Pin::new(&mut buf[..]))), Err(Elapsed));
+    }
+}
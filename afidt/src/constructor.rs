@@ -0,0 +1,253 @@
+//! Construct `dyn` object in arbitaray containers.
+
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::NonNull;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+
+use crate::{Emplaced, INLINE_LEN, VoidPtr};
+
+pub struct Constructor<Dyn: ?Sized, Args> {
+    layout: Layout,
+    args: Args,
+    init: unsafe fn(VoidPtr, Args) -> Emplaced<Dyn>,
+}
+
+impl<Dyn: ?Sized, Args> Constructor<Dyn, Args> {
+    pub unsafe fn new(
+        layout: Layout,
+        args: Args,
+        init: unsafe fn(VoidPtr, Args) -> Emplaced<Dyn>,
+    ) -> Self {
+        Self { layout, args, init }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Constructs the `dyn` object in the supplied slot.
+    ///
+    /// # Safety
+    ///
+    /// 1. `slot` must have enough space to fit the [`layout`] of the object.
+    /// 2. `slot` must be exclusive for this construction.
+    ///
+    /// [`layout`]: Self::layout
+    pub unsafe fn emplace(self, slot: VoidPtr) -> NonNull<Dyn> {
+        (self.init)(slot, self.args).into_ptr(slot)
+    }
+
+    pub fn init<C>(self, container: C) -> C::Ptr
+    where
+        C: Container<Dyn>,
+    {
+        container.init(self)
+    }
+
+    pub fn try_init<C>(self, container: C) -> Result<C::Ptr, C::Err<Args>>
+    where
+        C: Container<Dyn>,
+    {
+        container.try_init(self)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn boxed(self) -> Box<Dyn> {
+        self.init(Boxed)
+    }
+
+    pub fn buffered(self, buf: &mut [u8]) -> Buffered<'_, Dyn> {
+        self.init(buf)
+    }
+
+    pub fn try_buffered(self, buf: &mut [u8]) -> Result<Buffered<'_, Dyn>, Self> {
+        self.try_init(buf)
+    }
+
+    pub fn pinned(self) -> PinConstructor<Dyn, Args> {
+        PinConstructor(self)
+    }
+}
+
+impl<Dyn: ?Sized, Args: Clone> Constructor<Dyn, Args> {
+    /// Duplicates the constructor.
+    ///
+    /// # Safety
+    ///
+    /// Objects constructed from the duplicates must not be alive at the
+    /// same time, as they may borrow the same `&mut` receiver.
+    pub unsafe fn clone_unchecked(&self) -> Self {
+        Self {
+            layout: self.layout,
+            args: self.args.clone(),
+            init: self.init,
+        }
+    }
+}
+
+/// A variant of [`Constructor`] that requires pinned pointers.
+///
+/// Awaiting it places the future inline in `N` bytes, see [`IntoFuture`].
+///
+/// [`IntoFuture`]: core::future::IntoFuture
+pub struct PinConstructor<Dyn: ?Sized, Args, const N: usize = INLINE_LEN>(Constructor<Dyn, Args>);
+impl<Dyn: ?Sized, Args, const N: usize> PinConstructor<Dyn, Args, N> {
+    pub fn layout(&self) -> Layout {
+        self.0.layout()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn boxed(self) -> Pin<Box<Dyn>> {
+        Box::into_pin(self.0.boxed())
+    }
+
+    pub fn buffered(self, buf: Pin<&mut [u8]>) -> Pin<Buffered<'_, Dyn>> {
+        self.0.init(buf)
+    }
+
+    pub fn try_buffered(self, buf: Pin<&mut [u8]>) -> Result<Pin<Buffered<'_, Dyn>>, Self> {
+        self.0.try_init(buf).map_err(Self)
+    }
+
+    pub fn unpinned(self) -> Constructor<Dyn, Args> {
+        self.0
+    }
+
+    /// Sets the inline capacity used when the constructor is awaited.
+    pub fn with_inline_len<const M: usize>(self) -> PinConstructor<Dyn, Args, M> {
+        PinConstructor(self.0)
+    }
+}
+
+/// A one-time container used to construct `dyn` objects.
+pub unsafe trait Container<Dyn: ?Sized>: Sized {
+    type Ptr;
+    type Err<Args>;
+
+    fn init<Args>(self, constructor: Constructor<Dyn, Args>) -> Self::Ptr {
+        self.try_init(constructor)
+            .unwrap_or_else(|_| panic!("failed to initialize"))
+    }
+
+    fn try_init<Args>(
+        self,
+        constructor: Constructor<Dyn, Args>,
+    ) -> Result<Self::Ptr, Self::Err<Args>>;
+}
+
+#[cfg(feature = "alloc")]
+pub struct Boxed;
+#[cfg(feature = "alloc")]
+unsafe impl<Dyn: ?Sized> Container<Dyn> for Boxed {
+    type Ptr = Box<Dyn>;
+    type Err<Args> = core::convert::Infallible;
+
+    fn try_init<Args>(
+        self,
+        constructor: Constructor<Dyn, Args>,
+    ) -> Result<Self::Ptr, Self::Err<Args>> {
+        let layout = constructor.layout();
+        let slot = match layout.size() {
+            0 => panic!("zero sized type is not supported"),
+            // SAFETY: `layout` is non-zero in size,
+            _ => unsafe { NonNull::new(alloc::alloc::alloc(layout)) }
+                .unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout)),
+        };
+        unsafe {
+            let ptr = constructor.emplace(slot.cast());
+            Ok(Box::from_raw(ptr.as_ptr()))
+        }
+    }
+}
+
+pub struct Buffered<'a, Dyn: ?Sized>(NonNull<Dyn>, PhantomData<&'a mut [u8]>);
+impl<Dyn: ?Sized> Drop for Buffered<'_, Dyn> {
+    fn drop(&mut self) {
+        unsafe { self.0.drop_in_place() }
+    }
+}
+impl<Dyn: ?Sized> Deref for Buffered<'_, Dyn> {
+    type Target = Dyn;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.0.as_ref() }
+    }
+}
+impl<Dyn: ?Sized> DerefMut for Buffered<'_, Dyn> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.0.as_mut() }
+    }
+}
+
+// normal buffer
+unsafe impl<'a, Dyn: ?Sized> Container<Dyn> for &'a mut [u8] {
+    type Ptr = Buffered<'a, Dyn>;
+    type Err<Args> = Constructor<Dyn, Args>;
+
+    fn try_init<Args>(
+        self,
+        constructor: Constructor<Dyn, Args>,
+    ) -> Result<Self::Ptr, Self::Err<Args>> {
+        let layout = constructor.layout();
+        let capacity = self.len();
+
+        let buf = self as *mut [u8] as *mut u8;
+        let buf_end = buf.wrapping_add(capacity);
+        let slot = buf.wrapping_add(buf.align_offset(layout.align()));
+        let slot_end = slot.wrapping_add(layout.size());
+
+        if slot_end > buf_end || buf < slot {
+            return Err(constructor);
+        }
+        unsafe {
+            let ptr = constructor.emplace(NonNull::new_unchecked(slot).cast());
+            Ok(Buffered(ptr, PhantomData))
+        }
+    }
+}
+
+// pinned buffer
+unsafe impl<'a, Dyn: ?Sized> Container<Dyn> for Pin<&'a mut [u8]> {
+    type Ptr = Pin<Buffered<'a, Dyn>>;
+    type Err<Args> = Constructor<Dyn, Args>;
+
+    fn try_init<Args>(
+        self,
+        constructor: Constructor<Dyn, Args>,
+    ) -> Result<Self::Ptr, Self::Err<Args>> {
+        self.get_mut()
+            .try_init(constructor)
+            .map(|ptr| unsafe { Pin::new_unchecked(ptr) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::block_on;
+
+    use super::*;
+    use crate::test::{call, hold};
+
+    #[test]
+    fn buffered() {
+        let mut buf = [0u8; 64];
+        let fut = call(|| async { 1 });
+        let layout = fut.layout();
+        let fut = fut.buffered(Pin::new(&mut buf[..]));
+        assert_eq!(Layout::for_value(&*fut), layout);
+        assert_eq!(block_on(fut), 1);
+
+        let fut = call(hold::<64>);
+        assert!(fut.try_buffered(Pin::new(&mut buf[..])).is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn boxed() {
+        assert_eq!(block_on(call(hold::<64>).boxed()), 64);
+    }
+}
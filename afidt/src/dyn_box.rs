@@ -0,0 +1,104 @@
+//! Simulated `dyn` objects on the heap.
+
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
+
+use crate::{DynCompatible, DynInit, FusedFuture};
+
+pub struct DynBox<T: ?Sized + DynCompatible>(ManuallyDrop<T::Object>);
+
+impl<T: ?Sized + DynCompatible> DynBox<T> {
+    pub fn init<Args>(init: DynInit<T, Args>) -> Self {
+        unsafe {
+            let layout = init.layout();
+            let slot = NonNull::new(alloc::alloc::alloc(layout))
+                .unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout));
+            let obj = init.init(slot.cast());
+            Self(ManuallyDrop::new(obj))
+        }
+    }
+
+    /// Pins the object, which is fine as the data it points to never moves.
+    pub fn as_pin_mut(&mut self) -> Pin<&mut T::Object> {
+        unsafe { Pin::new_unchecked(&mut self.0) }
+    }
+}
+impl<T: ?Sized + DynCompatible> Unpin for DynBox<T> {}
+impl<T: ?Sized + DynCompatible> Deref for DynBox<T> {
+    type Target = T::Object;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<T: ?Sized + DynCompatible> DerefMut for DynBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+impl<T: ?Sized + DynCompatible> Drop for DynBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let obj = ManuallyDrop::take(&mut self.0);
+            let ptr = T::data(&obj);
+            let layout = T::layout(&obj);
+            drop(obj);
+            alloc::alloc::dealloc(ptr.as_ptr().cast(), layout);
+        }
+    }
+}
+impl<T: ?Sized + DynCompatible<Object: Future>> Future for DynBox<T> {
+    type Output = <T::Object as Future>::Output;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: the object lives on the heap, and is never moved out.
+        unsafe { Pin::new_unchecked(&mut *self.0) }.poll(cx)
+    }
+}
+impl<T: ?Sized + DynCompatible<Object: FusedFuture>> FusedFuture for DynBox<T> {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+impl<T: ?Sized + DynCompatible<Object: fmt::Debug>> fmt::Debug for DynBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DynBox").field(&*self.0).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use pollster::block_on;
+
+    use super::*;
+    use crate::CoerceDyn;
+    use crate::test::Tracked;
+
+    #[test]
+    fn init() {
+        let drops = AtomicU32::new(0);
+        let init = unsafe {
+            DynInit::<dyn Future<Output = u32>, _>::new(
+                &drops,
+                Layout::new::<Tracked>,
+                |slot, drops| {
+                    slot.cast().write(Tracked(Some(1), drops));
+                    <Tracked as CoerceDyn<dyn Future<Output = u32>>>::construct(slot)
+                },
+            )
+        };
+        let mut obj = DynBox::init(init);
+        assert_eq!(
+            <dyn Future<Output = u32>>::layout(&obj),
+            Layout::new::<Tracked>()
+        );
+        assert_eq!(block_on(obj.as_pin_mut()), 1);
+        drop(obj);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,29 @@
+//! Wrap simulated `dyn` objects.
+
+use core::alloc::Layout;
+
+use crate::{DynCompatible, VoidPtr};
+
+pub struct DynInit<Dyn: ?Sized + DynCompatible, Args> {
+    args: Args,
+    layout: fn() -> Layout,
+    init: fn(VoidPtr, Args) -> Dyn::Object,
+}
+
+impl<Dyn: ?Sized + DynCompatible, Args> DynInit<Dyn, Args> {
+    pub unsafe fn new(
+        args: Args,
+        layout: fn() -> Layout,
+        init: fn(VoidPtr, Args) -> Dyn::Object,
+    ) -> Self {
+        Self { args, layout, init }
+    }
+
+    pub fn layout(&self) -> Layout {
+        (self.layout)()
+    }
+
+    pub unsafe fn init(self, slot: VoidPtr) -> Dyn::Object {
+        (self.init)(slot, self.args)
+    }
+}
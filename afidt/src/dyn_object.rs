@@ -0,0 +1,511 @@
+//! Simulate `dyn` objects with manual vtables.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::marker::PhantomPinned;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
+
+#[cfg(feature = "nightly")]
+use crate::Emplaced;
+use crate::VoidPtr;
+
+/// Implemented by `dyn` types, whose objects are simulated by `Object`.
+pub unsafe trait DynCompatible {
+    type Object;
+
+    fn data(this: &Self::Object) -> VoidPtr;
+    fn layout(this: &Self::Object) -> Layout;
+}
+
+/// Implemented by `dyn` types for the concrete types that can be erased as
+/// them, which also works for `dyn` types defined downstream, unlike
+/// implementing [`CoerceDyn`] for the concrete types.
+pub unsafe trait CoerceFrom<T>: DynCompatible {
+    unsafe fn construct(data: VoidPtr) -> Self::Object;
+}
+
+/// Implemented by concrete types that can be erased as `Dyn`, for each
+/// [`CoerceFrom`] impl of `Dyn`.
+pub unsafe trait CoerceDyn<Dyn: ?Sized + DynCompatible>: Sized {
+    unsafe fn construct(data: VoidPtr) -> Dyn::Object;
+}
+unsafe impl<T, Dyn: ?Sized + CoerceFrom<T>> CoerceDyn<Dyn> for T {
+    unsafe fn construct(data: VoidPtr) -> Dyn::Object {
+        <Dyn as CoerceFrom<T>>::construct(data)
+    }
+}
+
+/// A future that knows whether it has completed, so that it won't be
+/// polled again after that.
+pub trait FusedFuture: Future {
+    fn is_terminated(&self) -> bool;
+}
+
+pub struct DynFuture<Fut: ?Sized + Future> {
+    data: VoidPtr,
+    vtable: *const FutureVtable<Fut>,
+    // the real `dyn` object, which replaces the manual entries of `vtable`
+    #[cfg(feature = "nightly")]
+    object: Emplaced<Fut>,
+    terminated: bool,
+    #[allow(dead_code)]
+    unpin: PhantomPinned,
+}
+struct FutureVtable<Fut: ?Sized + Future> {
+    #[cfg(not(feature = "nightly"))]
+    layout: fn() -> Layout,
+    type_name: fn() -> &'static str,
+    #[cfg(not(feature = "nightly"))]
+    poll_fn: unsafe fn(VoidPtr, cx: &mut Context) -> Poll<Fut::Output>,
+    #[cfg(not(feature = "nightly"))]
+    poll_into_fn:
+        unsafe fn(VoidPtr, cx: &mut Context, out: &mut MaybeUninit<Fut::Output>) -> Poll<()>,
+    #[cfg(not(feature = "nightly"))]
+    drop_fn: unsafe fn(VoidPtr),
+    // only present if the concrete type implements the trait
+    debug_fn: Option<unsafe fn(VoidPtr, &mut fmt::Formatter) -> fmt::Result>,
+    is_terminated_fn: Option<unsafe fn(VoidPtr) -> bool>,
+    #[cfg(feature = "nightly")]
+    _marker: core::marker::PhantomData<fn() -> Fut::Output>,
+}
+
+impl<T> FutureVtable<dyn Future<Output = T>> {
+    const fn new<Fut: Future<Output = T>>() -> Self {
+        // see <https://github.com/dtolnay/anyhow/blob/69295727cefb015a184f9b780fcc51ef905a798c/src/error.rs#L155>
+        #[cfg(not(feature = "nightly"))]
+        unsafe fn poll_fn<Fut: Future>(data: VoidPtr, cx: &mut Context) -> Poll<Fut::Output> {
+            Pin::new_unchecked(data.cast::<Fut>().as_mut()).poll(cx)
+        }
+        #[cfg(not(feature = "nightly"))]
+        unsafe fn poll_into_fn<Fut: Future>(
+            data: VoidPtr,
+            cx: &mut Context,
+            out: &mut MaybeUninit<Fut::Output>,
+        ) -> Poll<()> {
+            match Pin::new_unchecked(data.cast::<Fut>().as_mut()).poll(cx) {
+                Poll::Ready(output) => Poll::Ready(_ = out.write(output)),
+                Poll::Pending => Poll::Pending,
+            }
+        }
+        #[cfg(not(feature = "nightly"))]
+        unsafe fn drop_fn<Fut: Future>(data: VoidPtr) {
+            data.cast::<Fut>().drop_in_place();
+        }
+        FutureVtable {
+            #[cfg(not(feature = "nightly"))]
+            layout: Layout::new::<Fut>,
+            type_name: core::any::type_name::<Fut>,
+            #[cfg(not(feature = "nightly"))]
+            poll_fn: poll_fn::<Fut>,
+            #[cfg(not(feature = "nightly"))]
+            poll_into_fn: poll_into_fn::<Fut>,
+            #[cfg(not(feature = "nightly"))]
+            drop_fn: drop_fn::<Fut>,
+            debug_fn: None,
+            is_terminated_fn: None,
+            #[cfg(feature = "nightly")]
+            _marker: core::marker::PhantomData,
+        }
+    }
+
+    const fn with_debug<Fut: Future<Output = T> + fmt::Debug>(self) -> Self {
+        unsafe fn debug_fn<Fut: fmt::Debug>(data: VoidPtr, f: &mut fmt::Formatter) -> fmt::Result {
+            data.cast::<Fut>().as_ref().fmt(f)
+        }
+        FutureVtable {
+            debug_fn: Some(debug_fn::<Fut>),
+            ..self
+        }
+    }
+
+    const fn with_fused<Fut: FusedFuture<Output = T>>(self) -> Self {
+        unsafe fn is_terminated_fn<Fut: FusedFuture>(data: VoidPtr) -> bool {
+            data.cast::<Fut>().as_ref().is_terminated()
+        }
+        FutureVtable {
+            is_terminated_fn: Some(is_terminated_fn::<Fut>),
+            ..self
+        }
+    }
+}
+unsafe impl<T> DynCompatible for dyn Future<Output = T> {
+    type Object = DynFuture<dyn Future<Output = T>>;
+
+    fn data(this: &Self::Object) -> VoidPtr {
+        this.data
+    }
+    fn layout(this: &Self::Object) -> Layout {
+        this.layout()
+    }
+}
+unsafe impl<Fut: Future> CoerceFrom<Fut> for dyn Future<Output = Fut::Output> {
+    unsafe fn construct(data: VoidPtr) -> DynFuture<Self> {
+        DynFuture::from_vtable(data.cast::<Fut>(), const { &FutureVtable::new::<Fut>() })
+    }
+}
+
+impl<T> DynFuture<dyn Future<Output = T>> {
+    unsafe fn from_vtable<Fut: Future<Output = T>>(
+        data: NonNull<Fut>,
+        vtable: *const FutureVtable<dyn Future<Output = T>>,
+    ) -> Self {
+        DynFuture {
+            data: data.cast(),
+            vtable,
+            #[cfg(feature = "nightly")]
+            object: Emplaced::<dyn '_ + Future<Output = T>>::new(data).transmute(),
+            terminated: false,
+            unpin: PhantomPinned,
+        }
+    }
+
+    /// Like [`CoerceDyn::construct`], but the object is also formatted
+    /// by the `Debug` impl of `Fut`.
+    pub unsafe fn construct_debug<Fut>(data: VoidPtr) -> Self
+    where
+        Fut: Future<Output = T> + fmt::Debug,
+    {
+        Self::from_vtable(
+            data.cast::<Fut>(),
+            const { &FutureVtable::new::<Fut>().with_debug::<Fut>() },
+        )
+    }
+
+    /// Like [`CoerceDyn::construct`], but the object also asks `Fut`
+    /// whether it has terminated.
+    pub unsafe fn construct_fused<Fut>(data: VoidPtr) -> Self
+    where
+        Fut: FusedFuture<Output = T>,
+    {
+        Self::from_vtable(
+            data.cast::<Fut>(),
+            const { &FutureVtable::new::<Fut>().with_fused::<Fut>() },
+        )
+    }
+
+    pub unsafe fn construct_debug_fused<Fut>(data: VoidPtr) -> Self
+    where
+        Fut: FusedFuture<Output = T> + fmt::Debug,
+    {
+        Self::from_vtable(
+            data.cast::<Fut>(),
+            const {
+                &FutureVtable::new::<Fut>()
+                    .with_debug::<Fut>()
+                    .with_fused::<Fut>()
+            },
+        )
+    }
+}
+
+impl<Fut: ?Sized + Future> Future for DynFuture<Fut> {
+    type Output = Fut::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        unsafe {
+            let this = self.get_unchecked_mut();
+            #[cfg(not(feature = "nightly"))]
+            let poll = ((*this.vtable).poll_fn)(this.data, cx);
+            #[cfg(feature = "nightly")]
+            let poll = Pin::new_unchecked(this.object().as_mut()).poll(cx);
+            this.terminated |= poll.is_ready();
+            poll
+        }
+    }
+}
+impl<Fut: ?Sized + Future> FusedFuture for DynFuture<Fut> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+            || unsafe { (*self.vtable).is_terminated_fn }
+                .is_some_and(|is_terminated| unsafe { is_terminated(self.data) })
+    }
+}
+impl<Fut: ?Sized + Future> fmt::Debug for DynFuture<Fut> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Erased<'a, Fut: ?Sized + Future>(&'a DynFuture<Fut>);
+        impl<Fut: ?Sized + Future> fmt::Debug for Erased<'_, Fut> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match unsafe { (*self.0.vtable).debug_fn } {
+                    Some(debug_fn) => unsafe { debug_fn(self.0.data, f) },
+                    None => f.write_str(".."),
+                }
+            }
+        }
+        f.debug_struct("DynFuture")
+            .field("type", &self.type_name())
+            .field("terminated", &self.is_terminated())
+            .field("future", &Erased(self))
+            .finish()
+    }
+}
+impl<Fut: ?Sized + Future> DynFuture<Fut> {
+    /// Rebuilds the real `dyn` pointer to the future.
+    #[cfg(feature = "nightly")]
+    fn object(&self) -> NonNull<Fut> {
+        unsafe { self.object.into_ptr(self.data) }
+    }
+
+    fn layout(&self) -> Layout {
+        #[cfg(not(feature = "nightly"))]
+        return unsafe { ((*self.vtable).layout)() };
+        #[cfg(feature = "nightly")]
+        return Layout::for_value(unsafe { self.object().as_ref() });
+    }
+
+    /// The name of the erased future type.
+    pub fn type_name(&self) -> &'static str {
+        unsafe { ((*self.vtable).type_name)() }
+    }
+
+    /// Polls the future, and writes the output into `out` in place when
+    /// it's ready, instead of returning it through the erased layers.
+    pub fn poll_into(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut MaybeUninit<Fut::Output>,
+    ) -> Poll<()> {
+        unsafe {
+            let this = self.get_unchecked_mut();
+            #[cfg(not(feature = "nightly"))]
+            let poll = ((*this.vtable).poll_into_fn)(this.data, cx, out);
+            #[cfg(feature = "nightly")]
+            let poll = match Pin::new_unchecked(this.object().as_mut()).poll(cx) {
+                Poll::Ready(output) => Poll::Ready(_ = out.write(output)),
+                Poll::Pending => Poll::Pending,
+            };
+            this.terminated |= poll.is_ready();
+            poll
+        }
+    }
+
+    /// Returns a future that writes the output into `out`, and resolves
+    /// to a reference to the written output.
+    ///
+    /// The output isn't dropped by `out`, so it's up to the caller to read
+    /// it out or drop it in place.
+    pub fn write_into<'a>(
+        self: Pin<&mut Self>,
+        out: &'a mut MaybeUninit<Fut::Output>,
+    ) -> WriteInto<'_, 'a, Fut> {
+        WriteInto {
+            fut: self,
+            out: Some(out),
+        }
+    }
+}
+
+pub struct WriteInto<'f, 'a, Fut: ?Sized + Future> {
+    fut: Pin<&'f mut DynFuture<Fut>>,
+    out: Option<&'a mut MaybeUninit<Fut::Output>>,
+}
+impl<'a, Fut: ?Sized + Future> Future for WriteInto<'_, 'a, Fut> {
+    type Output = &'a mut Fut::Output;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self { fut, out } = &mut *self;
+        let slot = out.as_deref_mut().expect("polled after completion");
+        if fut.as_mut().poll_into(cx, slot).is_pending() {
+            return Poll::Pending;
+        }
+        Poll::Ready(unsafe { out.take().unwrap().assume_init_mut() })
+    }
+}
+
+impl<Fut: ?Sized + Future> Drop for DynFuture<Fut> {
+    fn drop(&mut self) {
+        // only drops the object, the memory of `data` is not freed
+        #[cfg(not(feature = "nightly"))]
+        unsafe {
+            ((*self.vtable).drop_fn)(self.data)
+        }
+        #[cfg(feature = "nightly")]
+        unsafe {
+            self.object().drop_in_place()
+        }
+    }
+}
+
+pub struct DynDebug {
+    data: VoidPtr,
+    #[cfg(not(feature = "nightly"))]
+    vtable: *const DebugVtable,
+    #[cfg(feature = "nightly")]
+    object: Emplaced<dyn fmt::Debug>,
+}
+#[cfg(not(feature = "nightly"))]
+struct DebugVtable {
+    layout: fn() -> Layout,
+    fmt_fn: unsafe fn(VoidPtr, &mut fmt::Formatter) -> fmt::Result,
+    drop_fn: unsafe fn(VoidPtr),
+}
+unsafe impl DynCompatible for dyn fmt::Debug {
+    type Object = DynDebug;
+
+    fn data(this: &Self::Object) -> VoidPtr {
+        this.data
+    }
+    fn layout(this: &Self::Object) -> Layout {
+        #[cfg(not(feature = "nightly"))]
+        return unsafe { ((*this.vtable).layout)() };
+        #[cfg(feature = "nightly")]
+        return Layout::for_value(unsafe { this.object.into_ptr(this.data).as_ref() });
+    }
+}
+unsafe impl<T: fmt::Debug> CoerceFrom<T> for dyn fmt::Debug {
+    unsafe fn construct(data: VoidPtr) -> DynDebug {
+        #[cfg(not(feature = "nightly"))]
+        let vtable = const {
+            &DebugVtable {
+                layout: Layout::new::<T>,
+                fmt_fn: |data, f| unsafe { data.cast::<T>().as_ref().fmt(f) },
+                drop_fn: |data| unsafe { data.cast::<T>().drop_in_place() },
+            }
+        };
+        DynDebug {
+            data,
+            #[cfg(not(feature = "nightly"))]
+            vtable,
+            #[cfg(feature = "nightly")]
+            object: Emplaced::<dyn '_ + fmt::Debug>::new(data.cast::<T>()).transmute(),
+        }
+    }
+}
+impl fmt::Debug for DynDebug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(not(feature = "nightly"))]
+        return unsafe { ((*self.vtable).fmt_fn)(self.data, f) };
+        #[cfg(feature = "nightly")]
+        return unsafe { self.object.into_ptr(self.data).as_ref() }.fmt(f);
+    }
+}
+impl Drop for DynDebug {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "nightly"))]
+        unsafe {
+            ((*self.vtable).drop_fn)(self.data)
+        }
+        #[cfg(feature = "nightly")]
+        unsafe {
+            self.object.into_ptr(self.data).drop_in_place()
+        }
+    }
+}
+
+/// Proves that the erased data is `Send`.
+pub struct DynSend {
+    data: VoidPtr,
+    #[cfg(not(feature = "nightly"))]
+    layout: fn() -> Layout,
+    #[cfg(not(feature = "nightly"))]
+    drop_fn: unsafe fn(VoidPtr),
+    #[cfg(feature = "nightly")]
+    object: Emplaced<dyn Send>,
+}
+unsafe impl Send for DynSend {}
+unsafe impl DynCompatible for dyn Send {
+    type Object = DynSend;
+
+    fn data(this: &Self::Object) -> VoidPtr {
+        this.data
+    }
+    fn layout(this: &Self::Object) -> Layout {
+        #[cfg(not(feature = "nightly"))]
+        return (this.layout)();
+        #[cfg(feature = "nightly")]
+        return Layout::for_value(unsafe { this.object.into_ptr(this.data).as_ref() });
+    }
+}
+unsafe impl<T: Send> CoerceFrom<T> for dyn Send {
+    unsafe fn construct(data: VoidPtr) -> DynSend {
+        DynSend {
+            data,
+            #[cfg(not(feature = "nightly"))]
+            layout: Layout::new::<T>,
+            #[cfg(not(feature = "nightly"))]
+            drop_fn: |data| unsafe { data.cast::<T>().drop_in_place() },
+            #[cfg(feature = "nightly")]
+            object: Emplaced::<dyn '_ + Send>::new(data.cast::<T>()).transmute(),
+        }
+    }
+}
+impl Drop for DynSend {
+    fn drop(&mut self) {
+        #[cfg(not(feature = "nightly"))]
+        unsafe {
+            (self.drop_fn)(self.data)
+        }
+        #[cfg(feature = "nightly")]
+        unsafe {
+            self.object.into_ptr(self.data).drop_in_place()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::test::Tracked;
+
+    fn erase<Fut: Future>(
+        slot: &mut MaybeUninit<Fut>,
+    ) -> DynFuture<dyn Future<Output = Fut::Output>> {
+        unsafe {
+            <Fut as CoerceDyn<dyn Future<Output = Fut::Output>>>::construct(
+                NonNull::from(slot).cast(),
+            )
+        }
+    }
+
+    #[test]
+    fn write_into_terminates() {
+        let mut slot = MaybeUninit::new(async { 1 });
+        let mut fut = core::pin::pin!(erase(&mut slot));
+        assert!(!fut.is_terminated());
+        let mut out = MaybeUninit::uninit();
+        assert_eq!(*pollster::block_on(fut.as_mut().write_into(&mut out)), 1);
+        assert!(fut.is_terminated());
+    }
+
+    #[test]
+    fn future_object() {
+        let drops = AtomicU32::new(0);
+        {
+            let mut slot = MaybeUninit::new(Tracked(Some(1), &drops));
+            let data = NonNull::from(&mut slot).cast();
+            let mut fut = pin!(unsafe { DynFuture::construct_debug_fused::<Tracked>(data) });
+            assert_eq!(
+                <dyn Future<Output = u32>>::layout(&fut),
+                Layout::new::<Tracked>()
+            );
+            assert_eq!(fut.type_name(), core::any::type_name::<Tracked>());
+            assert!(std::format!("{fut:?}").contains("Tracked(Some(1)"));
+            assert_eq!(pollster::block_on(fut.as_mut()), 1);
+            assert!(fut.is_terminated());
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn debug_object() {
+        let mut slot = MaybeUninit::new([1, 2]);
+        let data = NonNull::from(&mut slot).cast();
+        let obj = unsafe { <[i32; 2] as CoerceDyn<dyn fmt::Debug>>::construct(data) };
+        assert_eq!(std::format!("{obj:?}"), "[1, 2]");
+        assert_eq!(<dyn fmt::Debug>::layout(&obj), Layout::new::<[i32; 2]>());
+    }
+
+    #[test]
+    fn send_object() {
+        let drops = AtomicU32::new(0);
+        let mut slot = MaybeUninit::new(Tracked(Some(1), &drops));
+        let data = NonNull::from(&mut slot).cast();
+        let obj = unsafe { <Tracked as CoerceDyn<dyn Send>>::construct(data) };
+        assert_eq!(<dyn Send>::layout(&obj), Layout::new::<Tracked>());
+        drop(obj);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,263 @@
+//! `#[repr(C)]` erased futures to cross dynamic library boundaries, where
+//! neither side can rely on the Rust ABI of the other.
+//!
+//! Every vtable only calls back into the side that created it, so objects
+//! are always freed by the allocator they were allocated from.
+
+use alloc::boxed::Box;
+use core::alloc::Layout;
+use core::future::Future;
+use core::marker::{PhantomData, PhantomPinned};
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::VoidPtr;
+
+/// A `#[repr(C)]` version of [`Layout`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FfiLayout {
+    pub size: usize,
+    pub align: usize,
+}
+
+impl From<Layout> for FfiLayout {
+    fn from(layout: Layout) -> Self {
+        Self {
+            size: layout.size(),
+            align: layout.align(),
+        }
+    }
+}
+
+impl FfiLayout {
+    pub fn to_layout(self) -> Option<Layout> {
+        Layout::from_size_align(self.size, self.align).ok()
+    }
+}
+
+/// A `#[repr(C)]` version of [`Waker`].
+#[repr(C)]
+pub struct FfiWaker {
+    data: *const (),
+    vtable: &'static FfiWakerVtable,
+}
+
+#[repr(C)]
+pub struct FfiWakerVtable {
+    clone: unsafe extern "C" fn(*const ()) -> FfiWaker,
+    wake: unsafe extern "C" fn(*const ()),
+    wake_by_ref: unsafe extern "C" fn(*const ()),
+    drop: unsafe extern "C" fn(*const ()),
+}
+
+unsafe impl Send for FfiWaker {}
+unsafe impl Sync for FfiWaker {}
+
+impl FfiWaker {
+    pub fn wake(self) {
+        let this = ManuallyDrop::new(self);
+        unsafe { (this.vtable.wake)(this.data) }
+    }
+
+    pub fn wake_by_ref(&self) {
+        unsafe { (self.vtable.wake_by_ref)(self.data) }
+    }
+}
+
+impl Clone for FfiWaker {
+    fn clone(&self) -> Self {
+        unsafe { (self.vtable.clone)(self.data) }
+    }
+}
+
+impl Drop for FfiWaker {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.data) }
+    }
+}
+
+// An `FfiWaker` lent from a `&Waker`, or owning a `Box<Waker>` once cloned.
+static BORROWED_WAKER: FfiWakerVtable = FfiWakerVtable {
+    clone: clone_waker,
+    wake: wake_by_ref_waker,
+    wake_by_ref: wake_by_ref_waker,
+    drop: drop_nothing,
+};
+static OWNED_WAKER: FfiWakerVtable = FfiWakerVtable {
+    clone: clone_waker,
+    wake: wake_waker,
+    wake_by_ref: wake_by_ref_waker,
+    drop: drop_waker,
+};
+
+unsafe extern "C" fn clone_waker(data: *const ()) -> FfiWaker {
+    let waker = (*data.cast::<Waker>()).clone();
+    FfiWaker {
+        data: Box::into_raw(Box::new(waker)).cast(),
+        vtable: &OWNED_WAKER,
+    }
+}
+unsafe extern "C" fn wake_waker(data: *const ()) {
+    Box::from_raw(data.cast_mut().cast::<Waker>()).wake()
+}
+unsafe extern "C" fn wake_by_ref_waker(data: *const ()) {
+    (*data.cast::<Waker>()).wake_by_ref()
+}
+unsafe extern "C" fn drop_waker(data: *const ()) {
+    drop(Box::from_raw(data.cast_mut().cast::<Waker>()))
+}
+unsafe extern "C" fn drop_nothing(_: *const ()) {}
+
+// A `Waker` borrowing an `FfiWaker`, or owning a `Box<FfiWaker>` once cloned.
+static BORROWED_FFI_WAKER: RawWakerVTable = RawWakerVTable::new(
+    clone_ffi_waker,
+    wake_by_ref_ffi_waker,
+    wake_by_ref_ffi_waker,
+    |_| {},
+);
+static OWNED_FFI_WAKER: RawWakerVTable = RawWakerVTable::new(
+    clone_ffi_waker,
+    wake_ffi_waker,
+    wake_by_ref_ffi_waker,
+    drop_ffi_waker,
+);
+
+unsafe fn clone_ffi_waker(data: *const ()) -> RawWaker {
+    let waker = (*data.cast::<FfiWaker>()).clone();
+    RawWaker::new(Box::into_raw(Box::new(waker)).cast(), &OWNED_FFI_WAKER)
+}
+unsafe fn wake_ffi_waker(data: *const ()) {
+    Box::from_raw(data.cast_mut().cast::<FfiWaker>()).wake()
+}
+unsafe fn wake_by_ref_ffi_waker(data: *const ()) {
+    (*data.cast::<FfiWaker>()).wake_by_ref()
+}
+unsafe fn drop_ffi_waker(data: *const ()) {
+    drop(Box::from_raw(data.cast_mut().cast::<FfiWaker>()))
+}
+
+/// A `#[repr(C)]` version of [`Context`], which lends an [`FfiWaker`] for
+/// the duration of a poll.
+#[repr(C)]
+pub struct FfiContext<'a> {
+    waker: *const FfiWaker,
+    _marker: PhantomData<&'a FfiWaker>,
+}
+
+impl FfiContext<'_> {
+    /// Lends the waker of `cx` to the other side of the boundary.
+    pub fn with<R>(cx: &mut Context<'_>, f: impl FnOnce(&mut FfiContext<'_>) -> R) -> R {
+        let waker = FfiWaker {
+            data: (cx.waker() as *const Waker).cast(),
+            vtable: &BORROWED_WAKER,
+        };
+        f(&mut FfiContext {
+            waker: &waker,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Polls with a [`Context`] whose waker calls back into the lent one.
+    pub fn with_context<R>(&mut self, f: impl FnOnce(&mut Context<'_>) -> R) -> R {
+        let raw = RawWaker::new(self.waker.cast(), &BORROWED_FFI_WAKER);
+        // SAFETY: the waker doesn't outlive `self`, and only clones of it do.
+        let waker = unsafe { Waker::from_raw(raw) };
+        f(&mut Context::from_waker(&waker))
+    }
+}
+
+/// A `#[repr(C)]` version of [`DynFuture`], which drives a future placed by
+/// the other side of the boundary.
+///
+/// [`DynFuture`]: crate::DynFuture
+#[repr(C)]
+pub struct FfiFuture<T> {
+    data: VoidPtr,
+    vtable: *const FfiFutureVtable<T>,
+    _pinned: PhantomData<PhantomPinned>,
+}
+
+#[repr(C)]
+pub struct FfiFutureVtable<T> {
+    layout: FfiLayout,
+    poll_into: unsafe extern "C" fn(VoidPtr, &mut FfiContext, *mut T) -> bool,
+    drop: unsafe extern "C" fn(VoidPtr),
+}
+
+impl<T> FfiFutureVtable<T> {
+    const fn new<Fut: Future<Output = T>>() -> Self {
+        unsafe extern "C" fn poll_into<Fut: Future>(
+            data: VoidPtr,
+            cx: &mut FfiContext,
+            out: *mut Fut::Output,
+        ) -> bool {
+            let fut = Pin::new_unchecked(data.cast::<Fut>().as_mut());
+            match cx.with_context(|cx| fut.poll(cx)) {
+                Poll::Ready(output) => {
+                    out.write(output);
+                    true
+                }
+                Poll::Pending => false,
+            }
+        }
+        unsafe extern "C" fn drop<Fut>(data: VoidPtr) {
+            data.cast::<Fut>().drop_in_place();
+        }
+        FfiFutureVtable {
+            layout: FfiLayout {
+                size: size_of::<Fut>(),
+                align: align_of::<Fut>(),
+            },
+            poll_into: poll_into::<Fut>,
+            drop: drop::<Fut>,
+        }
+    }
+}
+
+impl<T> FfiFuture<T> {
+    /// Erases the future of `Fut` at `data`.
+    pub unsafe fn new<Fut: Future<Output = T>>(data: VoidPtr) -> Self {
+        Self {
+            data,
+            vtable: const { &FfiFutureVtable::new::<Fut>() },
+            _pinned: PhantomData,
+        }
+    }
+
+    /// Moves `fut` into `slot` and erases it.
+    ///
+    /// # Safety
+    ///
+    /// `slot` must fit the layout of `Fut`, and be exclusive for the future.
+    pub unsafe fn emplace<Fut: Future<Output = T>>(slot: VoidPtr, fut: Fut) -> Self {
+        slot.cast::<Fut>().write(fut);
+        Self::new::<Fut>(slot)
+    }
+
+    pub fn layout(&self) -> FfiLayout {
+        unsafe { (*self.vtable).layout }
+    }
+}
+
+impl<T> Future for FfiFuture<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut out = MaybeUninit::uninit();
+        let ready = FfiContext::with(cx, |cx| unsafe {
+            ((*self.vtable).poll_into)(self.data, cx, out.as_mut_ptr())
+        });
+        match ready {
+            true => Poll::Ready(unsafe { out.assume_init() }),
+            false => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for FfiFuture<T> {
+    fn drop(&mut self) {
+        // only drops the object, the slot belongs to the caller
+        unsafe { ((*self.vtable).drop)(self.data) }
+    }
+}
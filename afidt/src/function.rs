@@ -0,0 +1,149 @@
+//! Retrieve return type from arbitrary functions.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::{CoerceDyn, DynCompatible, VoidPtr};
+
+pub trait Function<Input> {
+    type Output;
+}
+
+macro_rules! impl_function {
+    ($($i:ident),* -> $o:ident) => {
+        impl<Fn, $($i,)* $o> Function<($($i,)*)> for Fn
+        where
+            Fn: FnOnce($($i,)*) -> $o,
+        {
+            type Output = $o;
+        }
+    };
+}
+impl_function!(A                -> R);
+impl_function!(A, B             -> R);
+impl_function!(A, B, C          -> R);
+impl_function!(A, B, C, D       -> R);
+impl_function!(A, B, C, D, E    -> R);
+impl_function!(A, B, C, D, E, F -> R);
+
+pub const fn return_type_dangling_ptr<I, F: Function<I>>(_: &F) -> *mut F::Output {
+    core::ptr::dangling_mut()
+}
+
+pub const fn return_type_layout<I, F: Function<I>>(_: &F) -> Layout {
+    Layout::new::<F::Output>()
+}
+
+pub const fn return_type_cast_ptr<I, F: Function<I>>(_: &F, ptr: VoidPtr) -> NonNull<F::Output> {
+    ptr.cast()
+}
+
+/// Simulates the `dyn` object of the return type of `F` whose data is at `data`.
+pub unsafe fn return_type_object<I, F: Function<I>, Dyn>(_: &F, data: VoidPtr) -> Dyn::Object
+where
+    Dyn: ?Sized + DynCompatible,
+    F::Output: CoerceDyn<Dyn>,
+{
+    <F::Output as CoerceDyn<Dyn>>::construct(data)
+}
+
+/// Returns the smallest layout that fits any of the given layouts.
+pub const fn max_layout(layouts: &[Layout]) -> Layout {
+    let (mut size, mut align, mut i) = (0, 1, 0);
+    while i < layouts.len() {
+        if layouts[i].size() > size {
+            size = layouts[i].size();
+        }
+        if layouts[i].align() > align {
+            align = layouts[i].align();
+        }
+        i += 1;
+    }
+    match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => panic!("layout overflow"),
+    }
+}
+
+/// Whether an object of `layout` fits in a byte buffer of `len` bytes,
+/// however the buffer is aligned.
+pub const fn fits_in_buffer(layout: Layout, len: usize) -> bool {
+    layout.size() + (layout.align() - 1) <= len
+}
+
+/// Fails the build if the future returned from a method of an implementor
+/// doesn't fit in a byte buffer of `len` bytes, rather than falling back
+/// to the heap at runtime.
+///
+/// ```ignore
+/// assert_future_fits!(File: AsyncRead::read, 128);
+/// ```
+///
+/// The `const` form is an inline `const` block for generic code, such as
+/// the erasure glue, which is checked once the impl is instantiated.
+// rustfmt moves the item of the second arm to column 0.
+#[rustfmt::skip]
+#[macro_export]
+macro_rules! assert_future_fits {
+    (const $ty:ty: $trait:ident::$method:ident, $len:expr) => {
+        const {
+            assert!(
+                $crate::fits_in_buffer($crate::return_type_layout(&<$ty as $trait>::$method), $len),
+                concat!(
+                    "the future of `<",
+                    stringify!($ty),
+                    " as ",
+                    stringify!($trait),
+                    ">::",
+                    stringify!($method),
+                    "` doesn't fit in ",
+                    stringify!($len),
+                    " bytes",
+                ),
+            )
+        }
+    };
+    ($ty:ty: $trait:ident::$method:ident, $len:expr) => {
+        const _: () = $crate::assert_future_fits!(const $ty: $trait::$method, $len);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use core::any::Any;
+    use core::convert::Infallible;
+    use std::boxed::Box;
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn test_return_type_layout() {
+        fn f1(_: usize, _: usize) -> usize {
+            todo!()
+        }
+        fn f2(_: &str) -> &str {
+            todo!()
+        }
+        fn f3(_: String, _: Vec<u8>, _: &dyn Any) -> Box<dyn Any> {
+            todo!()
+        }
+        fn f4(_: usize, _: usize) -> Infallible {
+            todo!()
+        }
+
+        assert_eq!(Layout::new::<usize>(), return_type_layout(&f1));
+        assert_eq!(Layout::new::<&str>(), return_type_layout(&f2));
+        assert_eq!(Layout::new::<Box<dyn Any>>(), return_type_layout(&f3));
+        assert_eq!(Layout::new::<Infallible>(), return_type_layout(&f4));
+
+        let layouts = [Layout::new::<[u8; 3]>(), Layout::new::<u16>()];
+        assert_eq!(Layout::from_size_align(3, 2).unwrap(), max_layout(&layouts));
+        assert_eq!(Layout::new::<()>(), max_layout(&[]));
+
+        assert!(fits_in_buffer(Layout::new::<u64>(), 15));
+        assert!(!fits_in_buffer(Layout::new::<u64>(), 14));
+        assert!(fits_in_buffer(Layout::new::<[u8; 8]>(), 8));
+    }
+}
@@ -0,0 +1,286 @@
+//! Await constructors directly.
+
+use core::alloc::Layout;
+use core::future::IntoFuture;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::{Buffered, PinConstructor};
+
+/// The inline capacity used when a [`PinConstructor`] is awaited directly,
+/// unless another one is picked in its type or by
+/// [`with_inline_len`](PinConstructor::with_inline_len).
+pub const INLINE_LEN: usize = 64;
+
+/// The placements falling back to the heap. A call site that must not fall
+/// back opts out with [`PinConstructor::no_heap`] instead.
+#[cfg(feature = "alloc")]
+mod heap_fallback {
+    use alloc::boxed::Box;
+
+    use super::*;
+
+    impl<Dyn: ?Sized + Future, Args, const N: usize> PinConstructor<Dyn, Args, N> {
+        /// Places the future inline if it fits in `M` bytes, or on the heap
+        /// otherwise.
+        pub fn inline_or_boxed<const M: usize>(self) -> InlineOrBoxed<Dyn, Args, M> {
+            InlineOrBoxed {
+                state: State::Init(self.with_inline_len()),
+                buf: [0; M],
+                _pinned: PhantomPinned,
+            }
+        }
+    }
+
+    /// Awaiting a constructor places the future with [`inline_or_boxed`]
+    /// in `N` bytes. Other placements are still available through the methods
+    /// of [`PinConstructor`].
+    ///
+    /// [`inline_or_boxed`]: PinConstructor::inline_or_boxed
+    impl<Dyn: ?Sized + Future, Args, const N: usize> IntoFuture for PinConstructor<Dyn, Args, N> {
+        type Output = Dyn::Output;
+        type IntoFuture = InlineOrBoxed<Dyn, Args, N>;
+
+        fn into_future(self) -> Self::IntoFuture {
+            self.inline_or_boxed()
+        }
+    }
+
+    /// A future that is constructed in its own inline buffer when first polled,
+    /// and falls back to the heap if the buffer is too small.
+    pub struct InlineOrBoxed<Dyn: ?Sized, Args, const N: usize> {
+        // Borrows `buf` rather than `'static` when it's buffered, which is
+        // fine as `buf` is pinned along with `self` and outlives the future.
+        state: State<Dyn, Args>,
+        buf: [u8; N],
+        _pinned: PhantomPinned,
+    }
+
+    enum State<Dyn: ?Sized, Args> {
+        Init(PinConstructor<Dyn, Args>),
+        Buffered(Pin<Buffered<'static, Dyn>>),
+        Boxed(Pin<Box<Dyn>>),
+        Done,
+    }
+
+    impl<Dyn: ?Sized + Future, Args, const N: usize> Future for InlineOrBoxed<Dyn, Args, N> {
+        type Output = Dyn::Output;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = unsafe { self.get_unchecked_mut() };
+            if let State::Init(_) = this.state {
+                let State::Init(constructor) = core::mem::replace(&mut this.state, State::Done)
+                else {
+                    unreachable!()
+                };
+                // SAFETY: `buf` doesn't move until `self` is dropped, and the
+                // future in it is dropped before that.
+                let buf = unsafe { Pin::new_unchecked(&mut *(&mut this.buf[..] as *mut [u8])) };
+                this.state = match constructor.try_buffered(buf) {
+                    Ok(fut) => State::Buffered(fut),
+                    Err(c) => State::Boxed(c.boxed()),
+                };
+            }
+            let poll = match &mut this.state {
+                State::Buffered(fut) => fut.as_mut().poll(cx),
+                State::Boxed(fut) => fut.as_mut().poll(cx),
+                State::Init(_) | State::Done => panic!("polled after completion"),
+            };
+            if poll.is_ready() {
+                this.state = State::Done;
+            }
+            poll
+        }
+    }
+}
+#[cfg(feature = "alloc")]
+pub use heap_fallback::*;
+
+/// Awaits a [`PinConstructor`] in a pinned stack buffer of the given size,
+/// or on the heap if the future doesn't fit in it.
+///
+/// ```ignore
+/// let item = dyn_await!(imp.foo(arg), stack = 64);
+/// ```
+///
+/// A call site that must not fall back uses [`try_dyn_await!`] instead.
+#[cfg(feature = "alloc")]
+#[macro_export]
+macro_rules! dyn_await {
+    ($constructor:expr, stack = $len:expr) => {{
+        let mut stack = ::core::pin::pin!([0u8; $len]);
+        match $constructor.try_buffered(stack.as_mut()) {
+            Ok(fut) => fut.await,
+            Err(c) => c.boxed().await,
+        }
+    }};
+}
+
+/// The error of placing a future under [`NoHeap`] where other placements
+/// would have fallen back to the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapFallback {
+    pub layout: Layout,
+}
+
+impl<Dyn: ?Sized, Args, const N: usize> PinConstructor<Dyn, Args, N> {
+    /// Forbids placing the object on the heap, see [`NoHeap`].
+    pub fn no_heap(self) -> NoHeap<Dyn, Args, N> {
+        NoHeap(self)
+    }
+}
+
+/// A [`PinConstructor`] that is never placed on the heap. It has no `boxed`,
+/// so an explicit fallback fails to compile, and placements that would
+/// silently fall back return a [`HeapFallback`] instead.
+pub struct NoHeap<Dyn: ?Sized, Args, const N: usize = INLINE_LEN>(PinConstructor<Dyn, Args, N>);
+impl<Dyn: ?Sized, Args, const N: usize> NoHeap<Dyn, Args, N> {
+    pub fn layout(&self) -> Layout {
+        self.0.layout()
+    }
+
+    pub fn try_buffered(self, buf: Pin<&mut [u8]>) -> Result<Pin<Buffered<'_, Dyn>>, HeapFallback> {
+        let layout = self.layout();
+        self.0
+            .try_buffered(buf)
+            .map_err(|_| HeapFallback { layout })
+    }
+}
+
+impl<Dyn: ?Sized + Future, Args, const N: usize> NoHeap<Dyn, Args, N> {
+    /// Places the future inline if it fits in `M` bytes, or fails with a
+    /// [`HeapFallback`] otherwise.
+    pub fn inline<const M: usize>(self) -> Inline<Dyn, Args, M> {
+        Inline {
+            state: InlineState::Init(self.0.with_inline_len()),
+            buf: [0; M],
+            _pinned: PhantomPinned,
+        }
+    }
+}
+
+/// Awaiting a [`NoHeap`] constructor places the future with [`inline`] in
+/// `N` bytes.
+///
+/// [`inline`]: NoHeap::inline
+impl<Dyn: ?Sized + Future, Args, const N: usize> IntoFuture for NoHeap<Dyn, Args, N> {
+    type Output = Result<Dyn::Output, HeapFallback>;
+    type IntoFuture = Inline<Dyn, Args, N>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        self.inline()
+    }
+}
+
+/// A future that is constructed in its own inline buffer when first polled,
+/// and resolves to a [`HeapFallback`] if the buffer is too small.
+pub struct Inline<Dyn: ?Sized, Args, const N: usize> {
+    state: InlineState<Dyn, Args>,
+    buf: [u8; N],
+    _pinned: PhantomPinned,
+}
+
+enum InlineState<Dyn: ?Sized, Args> {
+    Init(PinConstructor<Dyn, Args>),
+    Buffered(Pin<Buffered<'static, Dyn>>),
+    Done,
+}
+
+impl<Dyn: ?Sized + Future, Args, const N: usize> Future for Inline<Dyn, Args, N> {
+    type Output = Result<Dyn::Output, HeapFallback>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let InlineState::Init(_) = this.state {
+            let InlineState::Init(constructor) =
+                core::mem::replace(&mut this.state, InlineState::Done)
+            else {
+                unreachable!()
+            };
+            let layout = constructor.layout();
+            // SAFETY: `buf` doesn't move until `self` is dropped, and the
+            // future in it is dropped before that.
+            let buf = unsafe { Pin::new_unchecked(&mut *(&mut this.buf[..] as *mut [u8])) };
+            match constructor.try_buffered(buf) {
+                Ok(fut) => this.state = InlineState::Buffered(fut),
+                Err(_) => return Poll::Ready(Err(HeapFallback { layout })),
+            }
+        }
+        let InlineState::Buffered(fut) = &mut this.state else {
+            panic!("polled after completion")
+        };
+        let poll = fut.as_mut().poll(cx);
+        if poll.is_ready() {
+            this.state = InlineState::Done;
+        }
+        poll.map(Ok)
+    }
+}
+
+/// Same as [`dyn_await!`], but evaluates to a [`HeapFallback`] rather than
+/// placing the future on the heap.
+///
+/// ```ignore
+/// let item = try_dyn_await!(imp.foo(arg), stack = 64)?;
+/// ```
+#[macro_export]
+macro_rules! try_dyn_await {
+    ($constructor:expr, stack = $len:expr) => {{
+        let mut stack = ::core::pin::pin!([0u8; $len]);
+        match $constructor.no_heap().try_buffered(stack.as_mut()) {
+            Ok(fut) => Ok(fut.await),
+            Err(e) => Err(e),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::block_on;
+
+    use super::*;
+    use crate::test::{call, hold};
+
+    #[test]
+    fn no_heap() {
+        assert_eq!(
+            block_on(async { call(|| async { 1 }).no_heap().await }),
+            Ok(1)
+        );
+
+        let fut = call(hold::<INLINE_LEN>);
+        let err = Err(HeapFallback {
+            layout: fut.layout(),
+        });
+        assert_eq!(block_on(async { fut.no_heap().await }), err);
+        let fut = call(hold::<INLINE_LEN>).no_heap();
+        assert_eq!(block_on(fut.inline::<{ 2 * INLINE_LEN }>()), Ok(INLINE_LEN));
+        let out = block_on(async { crate::try_dyn_await!(call(hold::<16>), stack = 64) });
+        assert_eq!(out, Ok(16));
+    }
+
+    #[test]
+    fn inline_len() {
+        // picked in the type, e.g. in the return type of an erased method
+        let fut: PinConstructor<_, _, { 2 * INLINE_LEN }> =
+            call(hold::<INLINE_LEN>).with_inline_len();
+        assert_eq!(block_on(async { fut.no_heap().await }), Ok(INLINE_LEN));
+
+        let fut = call(hold::<INLINE_LEN>).with_inline_len::<{ 2 * INLINE_LEN }>();
+        let fut = fut.map(|n| n + 1).no_heap();
+        assert_eq!(block_on(async { fut.await }), Ok(INLINE_LEN + 1));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn inline_or_boxed() {
+        assert_eq!(block_on(async { call(hold::<16>).await }), 16);
+        assert_eq!(
+            block_on(async { call(hold::<INLINE_LEN>).await }),
+            INLINE_LEN
+        );
+        let out = block_on(async { crate::dyn_await!(call(hold::<64>), stack = 64) });
+        assert_eq!(out, 64);
+    }
+}
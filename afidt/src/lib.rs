@@ -0,0 +1,108 @@
+//! The placement machinery of the stable examples in `afidt-pin-init`, split
+//! out as a `#![no_std]` library.
+//!
+//! Everything builds on `core` only. Heap containers, i.e. [`Boxed`] and
+//! [`DynBox`], are behind the `alloc` feature, which is enabled by default.
+//!
+//! Awaiting a [`PinConstructor`] directly and [`dyn_await!`] place the future
+//! on the heap when it doesn't fit inline. A call site opts out of that with
+//! [`NoHeap`] and [`try_dyn_await!`], where a fallback is a [`HeapFallback`]
+//! error, or with [`assert_future_fits!`], where it fails to compile.
+//!
+//! The `nightly` feature switches [`Constructor`] and the simulated objects,
+//! e.g. [`DynFuture`], to real `dyn` pointers from `ptr::from_raw_parts_mut`,
+//! see [`Emplaced`]. The API stays the same.
+#![no_std]
+#![cfg_attr(feature = "nightly", feature(ptr_metadata))]
+#![allow(unsafe_op_in_unsafe_fn)]
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use core::ptr::NonNull;
+
+mod backend;
+mod combinator;
+mod constructor;
+#[cfg(feature = "alloc")]
+mod dyn_box;
+mod dyn_init;
+mod dyn_object;
+#[cfg(feature = "alloc")]
+mod ffi;
+mod function;
+mod into_future;
+mod retry;
+
+pub use backend::*;
+pub use combinator::*;
+pub use constructor::*;
+#[cfg(feature = "alloc")]
+pub use dyn_box::*;
+pub use dyn_init::*;
+pub use dyn_object::*;
+#[cfg(feature = "alloc")]
+pub use ffi::*;
+pub use function::*;
+pub use into_future::*;
+pub use retry::*;
+
+pub type VoidPtr = NonNull<Void>;
+pub enum Void {}
+
+#[cfg(test)]
+mod test {
+    use core::alloc::Layout;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use core::task::{Context, Poll};
+
+    use crate::{Constructor, Emplaced, FusedFuture, PinConstructor};
+
+    /// Constructs the future returned from `f`.
+    pub fn call<'a, F, Fut>(f: F) -> PinConstructor<dyn 'a + Future<Output = Fut::Output>, F>
+    where
+        F: FnOnce() -> Fut,
+        Fut: 'a + Future,
+    {
+        unsafe {
+            Constructor::new(Layout::new::<Fut>(), f, |slot, f| {
+                let slot = slot.cast::<Fut>();
+                slot.write(f());
+                Emplaced::new(slot)
+            })
+        }
+        .pinned()
+    }
+
+    /// A future that keeps `N` bytes across an await point.
+    pub async fn hold<const N: usize>() -> usize {
+        let buf = [0u8; N];
+        core::future::ready(()).await;
+        core::hint::black_box(&buf).len()
+    }
+
+    /// A ready future that counts its drops in a borrowed counter, so that
+    /// it isn't `'static`.
+    #[derive(Debug)]
+    pub struct Tracked<'a>(pub Option<u32>, pub &'a AtomicU32);
+    impl Future for Tracked<'_> {
+        type Output = u32;
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<u32> {
+            Poll::Ready(self.0.take().expect("polled after completion"))
+        }
+    }
+    impl FusedFuture for Tracked<'_> {
+        fn is_terminated(&self) -> bool {
+            self.0.is_none()
+        }
+    }
+    impl Drop for Tracked<'_> {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
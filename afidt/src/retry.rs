@@ -0,0 +1,184 @@
+//! Retry constructors in place.
+
+use core::future::{Ready, ready};
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::{Context, Poll};
+
+use crate::{Composed, ComposedPinConstructor, Constructor, INLINE_LEN, PinConstructor};
+
+/// Decides whether a failed attempt of a [`RetryConstructor`] is retried.
+pub trait RetryPolicy<T> {
+    type Delay: Future<Output = ()>;
+
+    /// Returns a delay to wait for before the next attempt, or `None` to
+    /// resolve with `output`. `attempts` counts the attempts made so far.
+    fn retry(&mut self, attempts: u32, output: &T) -> Option<Self::Delay>;
+}
+
+/// Retries errors until the given number of attempts has been made.
+pub struct Attempts(pub u32);
+
+impl<T, E> RetryPolicy<Result<T, E>> for Attempts {
+    type Delay = Ready<()>;
+
+    fn retry(&mut self, attempts: u32, output: &Result<T, E>) -> Option<Self::Delay> {
+        (output.is_err() && attempts < self.0).then(|| ready(()))
+    }
+}
+
+impl<T, F> RetryPolicy<T> for F
+where
+    F: FnMut(u32, &T) -> bool,
+{
+    type Delay = Ready<()>;
+
+    fn retry(&mut self, attempts: u32, output: &T) -> Option<Self::Delay> {
+        self(attempts, output).then(|| ready(()))
+    }
+}
+
+/// Waits for the future returned from `delay` before each retry that
+/// `policy` decides to make.
+pub struct Backoff<P, F> {
+    policy: P,
+    delay: F,
+}
+
+impl<P, F> Backoff<P, F> {
+    pub fn new(policy: P, delay: F) -> Self {
+        Self { policy, delay }
+    }
+}
+
+impl<T, P, F, D> RetryPolicy<T> for Backoff<P, F>
+where
+    P: RetryPolicy<T>,
+    F: FnMut(u32) -> D,
+    D: Future<Output = ()>,
+{
+    type Delay = D;
+
+    fn retry(&mut self, attempts: u32, output: &T) -> Option<Self::Delay> {
+        // replaces the delay of `policy`
+        drop(self.policy.retry(attempts, output)?);
+        Some((self.delay)(attempts))
+    }
+}
+
+/// A constructor whose future re-emplaces itself in the same slot for each
+/// attempt.
+pub type RetryConstructor<Dyn, Args, P, const N: usize = INLINE_LEN> =
+    ComposedPinConstructor<Retry<Dyn, Args, P>, Dyn, Args, N>;
+
+impl<Dyn: ?Sized + Future, Args: Clone, const N: usize> PinConstructor<Dyn, Args, N> {
+    /// Retries the future as `policy` decides, by dropping the last attempt
+    /// and constructing a new one where it was.
+    ///
+    /// Constructors that can only construct one future, such as the ones
+    /// calling an `FnOnce`, must not have `Clone` args to be retried with.
+    pub fn retry<P>(self, policy: P) -> RetryConstructor<Dyn, Args, P, N>
+    where
+        P: RetryPolicy<Dyn::Output>,
+    {
+        let template = self.unpinned();
+        // SAFETY: the template is never constructed, so only one attempt is
+        // alive at a time.
+        let first = unsafe { template.clone_unchecked() };
+        first
+            .compose(Retry {
+                template,
+                policy,
+                attempts: 1,
+                delay: None,
+            })
+            .pinned()
+            .with_inline_len()
+    }
+}
+
+pub struct Retry<Dyn, Args, P>
+where
+    Dyn: ?Sized + Future,
+    P: RetryPolicy<Dyn::Output>,
+{
+    template: Constructor<Dyn, Args>,
+    policy: P,
+    attempts: u32,
+    delay: Option<P::Delay>,
+}
+
+impl<Dyn, Args, P> Future for Composed<Retry<Dyn, Args, P>, Dyn>
+where
+    Dyn: ?Sized + Future,
+    Args: Clone,
+    P: RetryPolicy<Dyn::Output>,
+{
+    type Output = Dyn::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let (state, mut inner) = self.project();
+        // SAFETY: `delay` is structurally pinned, and others are never pinned.
+        let state = unsafe { state.get_unchecked_mut() };
+        loop {
+            if let Some(delay) = &mut state.delay {
+                core::task::ready!(unsafe { Pin::new_unchecked(delay) }.poll(cx));
+                state.delay = None;
+                unsafe {
+                    let next = state.template.clone_unchecked();
+                    let slot = inner.as_mut().get_unchecked_mut() as *mut Dyn;
+                    // Dropping the last attempt and constructing the next one
+                    // must not unwind, or the slot would be dropped twice.
+                    let guard = AbortOnUnwind;
+                    slot.drop_in_place();
+                    let ptr = next.emplace(NonNull::new_unchecked(slot).cast());
+                    debug_assert_eq!(ptr.as_ptr() as *mut u8, slot as *mut u8);
+                    core::mem::forget(guard);
+                }
+                state.attempts += 1;
+            }
+            let output = core::task::ready!(inner.as_mut().poll(cx));
+            match state.policy.retry(state.attempts, &output) {
+                Some(delay) => state.delay = Some(delay),
+                None => return Poll::Ready(output),
+            }
+        }
+    }
+}
+
+struct AbortOnUnwind;
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        // Panicking while unwinding aborts, which `core` can't do otherwise.
+        panic!("unwound while re-emplacing an attempt")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use pollster::block_on;
+
+    use super::*;
+    use crate::test::call;
+
+    #[test]
+    fn retry_in_place() {
+        let calls = &Cell::new(0);
+        let flaky = move || async move {
+            calls.set(calls.get() + 1);
+            if calls.get() % 3 == 0 {
+                Ok(calls.get())
+            } else {
+                Err(calls.get())
+            }
+        };
+        let mut buf = [0u8; 64];
+        let fut = call(flaky).retry(Attempts(5));
+        assert_eq!(block_on(fut.buffered(Pin::new(&mut buf[..]))), Ok(3));
+        let fut = call(flaky).retry(Attempts(2));
+        assert_eq!(block_on(fut.buffered(Pin::new(&mut buf[..]))), Err(5));
+        assert_eq!(calls.get(), 5);
+    }
+}
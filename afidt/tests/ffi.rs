@@ -0,0 +1,93 @@
+//! Builds the `cdylib` in `tests/plugin`, loads it with `dlopen` and drives
+//! its `AsyncRead` implementation through the `#[repr(C)]` interface.
+#![cfg(all(unix, feature = "alloc"))]
+
+use std::ffi::{CString, c_char, c_int, c_void};
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
+use std::process::Command;
+use std::ptr::NonNull;
+
+use afidt::fits_in_buffer;
+
+#[path = "plugin/src/abi.rs"]
+mod abi;
+use abi::*;
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+const RTLD_NOW: c_int = 2;
+
+fn build_plugin() -> PathBuf {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = root.join("target/plugin");
+    let status = Command::new(env!("CARGO"))
+        .arg("build")
+        .arg("--manifest-path")
+        .arg(root.join("tests/plugin/Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the plugin");
+    let name = format!(
+        "{}afidt_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    target_dir.join("debug").join(name)
+}
+
+/// The host side of [`FfiAsyncRead`], which places the returned futures in
+/// its own stack.
+struct Plugin(FfiAsyncRead);
+
+impl Plugin {
+    async fn read(&mut self, buf: &mut [u8]) -> isize {
+        #[repr(align(16))]
+        struct Stack(MaybeUninit<[u8; 256]>);
+
+        let FfiAsyncRead { data, vtable } = self.0;
+        let layout = unsafe { (vtable.read_layout)(data) }.to_layout().unwrap();
+        assert!(fits_in_buffer(layout, 256) && layout.align() <= 16);
+        let mut stack = Stack(MaybeUninit::uninit());
+        let slot = NonNull::new(stack.0.as_mut_ptr()).unwrap().cast();
+        let fut = unsafe { (vtable.read)(data, slot, buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(fut.layout(), layout.into());
+        fut.await
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe { (self.0.vtable.drop)(self.0.data) }
+    }
+}
+
+#[test]
+fn test_ffi() {
+    let path = CString::new(build_plugin().into_os_string().into_encoded_bytes()).unwrap();
+    let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+    assert!(!handle.is_null(), "failed to load the plugin");
+    let new = unsafe { dlsym(handle, NEW_SYMBOL.as_ptr()) };
+    assert!(!new.is_null(), "missing `{NEW_SYMBOL:?}`");
+    let new = unsafe { std::mem::transmute::<*mut c_void, NewFn>(new) };
+
+    let content = b"hello, plugin!";
+    let mut file = Plugin(unsafe { new(content.as_ptr(), content.len()) });
+    let mut buf = [0u8; 8];
+    let mut read = Vec::new();
+    pollster::block_on(async {
+        loop {
+            match file.read(&mut buf).await {
+                0 => break,
+                n if n > 0 => read.extend_from_slice(&buf[..n as usize]),
+                _ => panic!("failed to read"),
+            }
+        }
+    });
+    assert_eq!(read, content);
+    // The plugin stays loaded, as its code may still be referred to by wakers.
+}
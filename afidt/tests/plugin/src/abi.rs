@@ -0,0 +1,24 @@
+//! The interface shared by the plugin and the host, which only relies on
+//! `#[repr(C)]` types.
+
+use std::ffi::CStr;
+
+use afidt::{FfiFuture, FfiLayout, VoidPtr};
+
+/// Reads into `buf`, and resolves to the number of bytes read, or `-1` on error.
+#[repr(C)]
+pub struct FfiAsyncRead {
+    pub data: *mut (),
+    pub vtable: &'static FfiAsyncReadVtable,
+}
+
+#[repr(C)]
+pub struct FfiAsyncReadVtable {
+    pub read_layout: unsafe extern "C" fn(*mut ()) -> FfiLayout,
+    pub read: unsafe extern "C" fn(*mut (), VoidPtr, *mut u8, usize) -> FfiFuture<isize>,
+    pub drop: unsafe extern "C" fn(*mut ()),
+}
+
+pub type NewFn = unsafe extern "C" fn(*const u8, usize) -> FfiAsyncRead;
+
+pub const NEW_SYMBOL: &CStr = c"afidt_plugin_new";
@@ -0,0 +1,92 @@
+//! A plugin implementing `AsyncRead` over an in-memory file, whose reads
+//! are woken up from another thread.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use afidt::{FfiFuture, FfiLayout, VoidPtr, return_type_layout};
+
+pub mod abi;
+use abi::*;
+
+trait AsyncRead {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+struct File {
+    content: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for File {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        WakeFromThread(false).await;
+        let rest = &self.content[self.pos..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Pending once, and woken up by a clone of the waker on another thread.
+struct WakeFromThread(bool);
+impl Future for WakeFromThread {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || waker.wake());
+        Poll::Pending
+    }
+}
+
+static VTABLE: FfiAsyncReadVtable = FfiAsyncReadVtable {
+    read_layout,
+    read,
+    drop,
+};
+
+async fn read_isize(this: &mut File, buf: &mut [u8]) -> isize {
+    match this.read(buf).await {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    }
+}
+
+unsafe extern "C" fn read_layout(_: *mut ()) -> FfiLayout {
+    return_type_layout(&read_isize).into()
+}
+
+unsafe extern "C" fn read(
+    this: *mut (),
+    slot: VoidPtr,
+    buf: *mut u8,
+    len: usize,
+) -> FfiFuture<isize> {
+    let this = unsafe { &mut *this.cast::<File>() };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+    unsafe { FfiFuture::emplace(slot, read_isize(this, buf)) }
+}
+
+unsafe extern "C" fn drop(this: *mut ()) {
+    unsafe { std::mem::drop(Box::from_raw(this.cast::<File>())) }
+}
+
+/// # Safety
+///
+/// `content` must be valid for reads of `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn afidt_plugin_new(content: *const u8, len: usize) -> FfiAsyncRead {
+    let content = unsafe { std::slice::from_raw_parts(content, len) }.to_vec();
+    FfiAsyncRead {
+        data: Box::into_raw(Box::new(File { content, pos: 0 })).cast(),
+        vtable: &VTABLE,
+    }
+}
+const _: NewFn = afidt_plugin_new;
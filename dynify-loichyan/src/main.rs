@@ -1,8 +1,12 @@
 use dynify::*;
 use std::{
-    future::Future,
+    future::{Future, poll_fn},
     mem::MaybeUninit,
+    ops::DerefMut,
     pin::{Pin, pin},
+    sync::{Arc, Mutex},
+    task::Poll,
+    thread,
 };
 
 pub trait UserCommunication {
@@ -35,6 +39,18 @@ fn main() {
     pollster::block_on(run());
 }
 
+/// Awaits a constructor in a pinned stack buffer of the given size, or on the
+/// heap if the future doesn't fit in it.
+macro_rules! dyn_await {
+    ($constructor:expr, stack = $len:expr) => {{
+        let stack = pin!(StackedBuf::<{ $len }>::UNINIT);
+        match $constructor.try_pin_init(StackedBuf::new(stack)) {
+            Ok(fut) => fut.await,
+            Err((c, _)) => c.pin_boxed().await,
+        }
+    }};
+}
+
 async fn run() {
     let auth = AuthenticationService {
         communicator: Box::new(Test { _a: 0 }),
@@ -46,6 +62,94 @@ async fn run() {
         .send_sms("abc", "123")
         .pin_init(StackedBuf::new(stack))
         .await;
+
+    dyn_await!(auth.communicator.send_sms("abc", "123"), stack = 128);
+    // falls back to the heap
+    dyn_await!(auth.communicator.send_sms("abc", "123"), stack = 8);
+
+    shared_calls().await;
+}
+
+// Concurrent calls on a shared `&self` service:
+//
+// `send_sms` takes `&self`, so many calls can be outstanding on the same
+// object, as long as each future gets its own slot. A future borrows the
+// service through the `&dyn` reborrowed from the `Arc`, so the `Arc` clone
+// must outlive both the future and the slot holding it.
+async fn shared_calls() {
+    let recorder = Arc::new(Recorder::default());
+    let shared: Arc<dyn DynUserCommunication + Send + Sync> = recorder.clone();
+
+    // a slab of pinned slots: one slot per outstanding call in the same task
+    let comm: &dyn DynUserCommunication = &*shared;
+    let mut slab = pin!(Slab::<3, FUT_STACK_LEN>::UNINIT);
+    let mut slots = slab.as_mut().slots().into_iter();
+    let mut futs = ["a", "b", "c"]
+        .map(|phone| Some(comm.send_sms(phone, "0").pin_init(slots.next().unwrap())));
+    join_all(&mut futs).await;
+    // all calls were in flight at the same time
+    assert_eq!(recorder.take()[..3], ["start a", "start b", "start c"]);
+
+    // per-task stack buffers: each task owns its `Arc` clone and its buffer
+    let tasks = ["d", "e", "f"].map(|phone| {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            pollster::block_on(async move {
+                let stack = pin!(StackedBuf::<FUT_STACK_LEN>::UNINIT);
+                shared
+                    .send_sms(phone, "1")
+                    .pin_init(StackedBuf::new(stack))
+                    .await;
+            })
+        })
+    });
+    for task in tasks {
+        task.join().unwrap();
+    }
+    let mut log = recorder.take();
+    log.sort();
+    assert_eq!(
+        log,
+        ["end d", "end e", "end f", "start d", "start e", "start f"]
+    );
+    println!("shared_calls pass");
+}
+
+/// A slab of pinned slots, split into `N` disjoint slots of `LEN` bytes.
+pub struct Slab<const N: usize, const LEN: usize>([[MaybeUninit<u8>; LEN]; N]);
+
+impl<const N: usize, const LEN: usize> Slab<N, LEN> {
+    const UNINIT: Self = Slab([StackedBuf::<LEN>::UNINIT; N]);
+
+    pub fn slots(self: Pin<&mut Self>) -> [StackedBuf<'_, LEN>; N] {
+        // SAFETY: slots are pinned projections of the slab, never moved out.
+        let slots = unsafe { &mut self.get_unchecked_mut().0 };
+        slots
+            .each_mut()
+            .map(|slot| StackedBuf::new(unsafe { Pin::new_unchecked(slot) }))
+    }
+}
+
+/// Polls all futures in turn until they are all ready.
+async fn join_all<P>(futs: &mut [Option<Pin<P>>])
+where
+    P: DerefMut<Target: Future<Output = ()>>,
+{
+    poll_fn(|cx| {
+        for slot in futs.iter_mut() {
+            if let Some(fut) = slot
+                && fut.as_mut().poll(cx).is_ready()
+            {
+                *slot = None;
+            }
+        }
+        if futs.iter().all(Option::is_none) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
 }
 
 const FUT_STACK_LEN: usize = 128;
@@ -82,3 +186,33 @@ impl UserCommunication for Test {
         println!("[{self:?}] {phone}: {code}")
     }
 }
+
+#[derive(Default)]
+struct Recorder {
+    log: Mutex<Vec<String>>,
+}
+impl Recorder {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.log.lock().unwrap())
+    }
+}
+impl UserCommunication for Recorder {
+    async fn send_sms(&self, phone: &str, _code: &str) {
+        self.log.lock().unwrap().push(format!("start {phone}"));
+        yield_now().await;
+        self.log.lock().unwrap().push(format!("end {phone}"));
+    }
+}
+
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,131 @@
+//! Real files and pipes for the examples. Their blocking reads run on a local
+//! worker thread, which wakes the task on completion, so the dispatch
+//! strategies are driven through real pending/wake cycles: every read through
+//! [`read_to_end`] is real I/O, pending until the worker thread wakes the task
+//! up.
+
+use std::{
+    io::{self, Write as _},
+    path::Path,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::Duration,
+};
+
+/// Reads everything with `read`, which reads through the dispatch strategy of
+/// the example, 16 bytes at a time.
+#[allow(dead_code)] // the extension pattern has its own `read_to_end`
+pub async fn read_to_end(
+    mut read: impl AsyncFnMut(&mut [u8]) -> io::Result<usize>,
+) -> io::Result<Vec<u8>> {
+    let (mut data, mut buf) = (Vec::new(), [0; 16]);
+    loop {
+        match read(&mut buf).await? {
+            0 => return Ok(data),
+            n => data.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+/// Opens a file whose reads happen on a worker thread.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Blocking> {
+    Ok(Blocking::new(std::fs::File::open(path)?))
+}
+
+/// Opens a pipe whose writing end is fed with `content` by another thread,
+/// a few bytes at a time, so that reads wait for the writer.
+pub fn pipe(content: &'static [u8]) -> io::Result<Blocking> {
+    let (reader, mut writer) = io::pipe()?;
+    thread::spawn(move || {
+        for chunk in content.chunks(4) {
+            thread::sleep(Duration::from_millis(1));
+            if writer.write_all(chunk).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Blocking::new(reader))
+}
+
+/// A reader whose blocking reads are sent to a worker thread.
+///
+/// Each read hands a buffer to the worker and is pending until the worker
+/// sends it back, so the buffer is reused rather than allocated per read.
+pub struct Blocking {
+    shared: Arc<Mutex<Shared>>,
+    requests: mpsc::Sender<Vec<u8>>,
+    in_flight: bool,
+    // bytes read by the worker that didn't fit in the caller's buffer
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+#[derive(Default)]
+struct Shared {
+    waker: Option<Waker>,
+    done: Option<(Vec<u8>, io::Result<usize>)>,
+}
+
+impl Blocking {
+    pub fn new(mut inner: impl io::Read + Send + 'static) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let (requests, rx) = mpsc::channel::<Vec<u8>>();
+        let worker = shared.clone();
+        // exits once `requests` is dropped along with `Blocking`
+        thread::spawn(move || {
+            for mut buf in rx {
+                let res = inner.read(&mut buf);
+                let mut shared = worker.lock().unwrap();
+                shared.done = Some((buf, res));
+                let waker = shared.waker.take();
+                // the woken task may poll right away, and lock `shared` again
+                drop(shared);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        });
+        Self {
+            shared,
+            requests,
+            in_flight: false,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.pos == self.buf.len() {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let mut shared = self.shared.lock().unwrap();
+            let Some((data, res)) = shared.done.take() else {
+                shared.waker = Some(cx.waker().clone());
+                drop(shared);
+                if !std::mem::replace(&mut self.in_flight, true) {
+                    let mut data = std::mem::take(&mut self.buf);
+                    self.pos = 0;
+                    data.resize(buf.len(), 0);
+                    self.requests.send(data).expect("the worker has exited");
+                }
+                return Poll::Pending;
+            };
+            drop(shared);
+            self.in_flight = false;
+            (self.buf, self.pos) = (data, 0);
+            match res {
+                Ok(n) => self.buf.truncate(n),
+                Err(e) => {
+                    self.buf.clear();
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        let n = (self.buf.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.buf[self.pos..][..n]);
+        self.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
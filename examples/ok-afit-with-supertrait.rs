@@ -1,12 +1,24 @@
 use core::{
-    pin::Pin,
+    marker::PhantomPinned,
+    pin::{pin, Pin},
     task::{Context, Poll},
 };
 use std::{future::poll_fn, io};
 
+#[path = "common/blocking.rs"]
+mod blocking;
+
 #[pollster::main]
 async fn main() {
     _ = dbg!(call(&mut _impl::File::new()).await);
+    _ = dbg!(call_pinned(pin!(_impl::PinnedFile::new())).await);
+
+    let file: &mut (dyn Read + Unpin) = &mut blocking::open(file!()).unwrap();
+    let data = blocking::read_to_end(async |buf| file.read(buf).await).await;
+    _ = dbg!(data.map(|data| data.len()));
+    let pipe: &mut (dyn Read + Unpin) = &mut blocking::pipe(b"hello through a pipe").unwrap();
+    let data = blocking::read_to_end(async |buf| pipe.read(buf).await).await;
+    _ = dbg!(data.map(String::from_utf8));
 }
 
 pub trait Read {
@@ -27,12 +39,31 @@ pub trait AsyncRead: Read + Unpin {
 
 impl<T: Read + Unpin + ?Sized> AsyncRead for T {}
 
+impl<P> Read for Pin<P>
+where
+    P: core::ops::DerefMut<Target: Read> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().as_mut().poll_read(cx, buf)
+    }
+}
+
 pub async fn call(file: &mut (dyn Read + Unpin)) -> io::Result<usize> {
     file.read(&mut []).await
 }
 
+/// `!Unpin` readers are read through `Pin<&mut dyn Read>`, which is itself
+/// an `Unpin` reader.
+pub async fn call_pinned(mut file: Pin<&mut dyn Read>) -> io::Result<usize> {
+    file.read(&mut [0; 4]).await
+}
+
 mod _impl {
-    use crate::{io, Context, Pin, Poll, Read};
+    use crate::{blocking::Blocking, io, Context, PhantomPinned, Pin, Poll, Read};
 
     pub struct File {}
 
@@ -51,4 +82,42 @@ mod _impl {
             Poll::Ready(Ok(buf.len()))
         }
     }
+
+    /// A file whose state is pinned, like the timers or the inner futures
+    /// embedded in real readers.
+    pub struct PinnedFile {
+        reads: usize,
+        _pinned: PhantomPinned,
+    }
+
+    impl PinnedFile {
+        pub fn new() -> Self {
+            Self {
+                reads: 0,
+                _pinned: PhantomPinned,
+            }
+        }
+    }
+
+    impl Read for PinnedFile {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            // SAFETY: `reads` isn't structurally pinned.
+            unsafe { self.get_unchecked_mut() }.reads += 1;
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    impl Read for Blocking {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Blocking::poll_read(self.get_mut(), cx, buf)
+        }
+    }
 }
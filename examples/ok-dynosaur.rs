@@ -1,5 +1,8 @@
 use std::io; // pretend a Result is defined somewhere without std
 
+#[path = "common/blocking.rs"]
+mod blocking;
+
 #[pollster::main]
 async fn main() -> io::Result<()> {
     let mut file = _impl::File::new();
@@ -17,6 +20,15 @@ async fn main() -> io::Result<()> {
     let mut box_dyn_async_read = DynAsyncRead::boxed(file);
     dbg!(call(&mut box_dyn_async_read).await?);
 
+    let mut file = blocking::open(file!())?;
+    let file = DynAsyncRead::from_mut(&mut file);
+    let data = blocking::read_to_end(async |buf| file.read(buf).await).await?;
+    dbg!(data.len());
+    let mut pipe = blocking::pipe(b"hello through a pipe")?;
+    let pipe = DynAsyncRead::from_mut(&mut pipe);
+    let data = blocking::read_to_end(async |buf| pipe.read(buf).await).await?;
+    dbg!(String::from_utf8(data));
+
     Ok(())
 }
 
@@ -31,7 +43,8 @@ pub async fn call(file: &mut DynAsyncRead<'_>) -> io::Result<usize> {
 }
 
 mod _impl {
-    use crate::{io, AsyncRead};
+    use crate::{blocking::Blocking, io, AsyncRead};
+    use std::future::poll_fn;
 
     pub struct File {}
 
@@ -50,6 +63,12 @@ mod _impl {
             self.inner_read(buf).await
         }
     }
+
+    impl AsyncRead for Blocking {
+        async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            poll_fn(|cx| self.poll_read(cx, buf)).await
+        }
+    }
 }
 
 // #[allow(async_fn_in_trait)]
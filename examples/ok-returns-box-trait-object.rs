@@ -1,6 +1,17 @@
 use std::io;
 
-fn main() {}
+#[path = "common/blocking.rs"]
+mod blocking;
+
+#[pollster::main]
+async fn main() {
+    let file: &mut dyn AsyncRead = &mut blocking::open(file!()).unwrap();
+    let data = blocking::read_to_end(async |buf| file.read(buf).await).await;
+    _ = dbg!(data.map(|data| data.len()));
+    let pipe: &mut dyn AsyncRead = &mut blocking::pipe(b"hello through a pipe").unwrap();
+    let data = blocking::read_to_end(async |buf| pipe.read(buf).await).await;
+    _ = dbg!(data.map(String::from_utf8));
+}
 
 #[async_trait::async_trait] // trait object safe
 pub trait AsyncRead {
@@ -11,3 +22,15 @@ pub trait AsyncRead {
 pub async fn call(file: &mut dyn AsyncRead) -> io::Result<usize> {
     file.read(&mut []).await
 }
+
+mod _impl {
+    use crate::{blocking::Blocking, io, AsyncRead};
+    use std::future::poll_fn;
+
+    #[async_trait::async_trait]
+    impl AsyncRead for Blocking {
+        async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            poll_fn(|cx| self.poll_read(cx, buf)).await
+        }
+    }
+}
@@ -1,11 +1,37 @@
 use core::{
     future::Future,
-    pin::Pin,
-    task::{Context, Poll},
+    marker::PhantomPinned,
+    mem::MaybeUninit,
+    ops::DerefMut,
+    pin::{pin, Pin},
+    task::{ready, Context, Poll},
 };
-use std::io;
+use std::io::{self, IoSliceMut, SeekFrom};
 
-fn main() {}
+#[path = "common/blocking.rs"]
+mod blocking;
+
+#[pollster::main]
+async fn main() {
+    let mut file = _impl::File::new(b"hello\nworld\n".to_vec());
+    _ = dbg!(call(&mut file).await);
+    _ = dbg!(call_write(&mut file).await);
+    _ = dbg!(call_seek(&mut file).await);
+    _ = dbg!(call_buf_read(&mut file).await);
+    _ = dbg!(call_pinned(pin!(_impl::PinnedFile::new())).await);
+
+    let file: &mut (dyn Read + Unpin) = &mut blocking::open(file!()).unwrap();
+    let mut data = Vec::new();
+    _ = dbg!(file.read_to_end(&mut data).await);
+    let pipe: &mut (dyn Read + Unpin) = &mut blocking::pipe(b"hello through a pipe").unwrap();
+    let mut data = vec![0; 20];
+    _ = dbg!(pipe
+        .read_exact(&mut data)
+        .await
+        .map(|()| String::from_utf8(data)));
+}
+
+// =========== poll-based traits ===========
 
 pub trait Read {
     fn poll_read(
@@ -13,6 +39,44 @@ pub trait Read {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>>;
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let buf = match bufs.iter_mut().find(|b| !b.is_empty()) {
+            Some(buf) => &mut **buf,
+            None => &mut [],
+        };
+        self.poll_read(cx, buf)
+    }
+}
+
+pub trait Write {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+pub trait BufRead: Read {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>>;
+
+    fn consume(self: Pin<&mut Self>, amt: usize);
+}
+
+pub trait Seek {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>>;
 }
 
 impl<T: ?Sized + Read + Unpin> Read for &mut T {
@@ -23,8 +87,116 @@ impl<T: ?Sized + Read + Unpin> Read for &mut T {
     ) -> Poll<io::Result<usize>> {
         Pin::new(&mut **self).poll_read(cx, buf)
     }
+
+    fn poll_read_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<T: ?Sized + Write + Unpin> Write for &mut T {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self).poll_close(cx)
+    }
+}
+
+impl<T: ?Sized + BufRead + Unpin> BufRead for &mut T {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut **self.get_mut()).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut **self).consume(amt)
+    }
+}
+
+impl<T: ?Sized + Seek + Unpin> Seek for &mut T {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        Pin::new(&mut **self).poll_seek(cx, pos)
+    }
+}
+
+// `Pin<P>` forwards to the pinned `!Unpin` object, and is `Unpin` itself, so
+// that the extension methods and named futures below work on `Pin<&mut T>`
+// and `Pin<&mut dyn Read>` without boxing.
+
+impl<P: DerefMut<Target: Read> + Unpin> Read for Pin<P> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().as_mut().poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().as_mut().poll_read_vectored(cx, bufs)
+    }
 }
 
+impl<P: DerefMut<Target: Write> + Unpin> Write for Pin<P> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().as_mut().poll_close(cx)
+    }
+}
+
+impl<P: DerefMut<Target: BufRead> + Unpin> BufRead for Pin<P> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().as_mut().poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().as_mut().consume(amt)
+    }
+}
+
+impl<P: DerefMut<Target: Seek> + Unpin> Seek for Pin<P> {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        self.get_mut().as_mut().poll_seek(cx, pos)
+    }
+}
+
+// =========== extension traits ===========
+
 pub trait AsyncRead: Read {
     fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadFuture<'a, Self>
     where
@@ -32,8 +204,126 @@ pub trait AsyncRead: Read {
     {
         ReadFuture { reader: self, buf }
     }
+
+    fn read_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadExactFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadExactFuture { reader: self, buf }
+    }
+
+    fn read_to_end<'a>(&'a mut self, buf: &'a mut Vec<u8>) -> ReadToEndFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        let start_len = buf.len();
+        ReadToEndFuture {
+            reader: self,
+            buf,
+            start_len,
+            initialized: 0,
+        }
+    }
+
+    fn read_vectored<'a, 'b>(
+        &'a mut self,
+        bufs: &'a mut [IoSliceMut<'b>],
+    ) -> ReadVectoredFuture<'a, 'b, Self>
+    where
+        Self: Unpin,
+    {
+        ReadVectoredFuture { reader: self, bufs }
+    }
+}
+
+pub trait AsyncWrite: Write {
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> WriteFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        WriteFuture { writer: self, buf }
+    }
+
+    fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> WriteAllFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        WriteAllFuture { writer: self, buf }
+    }
+
+    fn flush(&mut self) -> FlushFuture<'_, Self>
+    where
+        Self: Unpin,
+    {
+        FlushFuture { writer: self }
+    }
+
+    fn close(&mut self) -> CloseFuture<'_, Self>
+    where
+        Self: Unpin,
+    {
+        CloseFuture { writer: self }
+    }
+}
+
+pub trait AsyncBufRead: BufRead {
+    fn fill_buf(&mut self) -> FillBufFuture<'_, Self>
+    where
+        Self: Unpin,
+    {
+        FillBufFuture { reader: Some(self) }
+    }
+
+    fn consume(&mut self, amt: usize)
+    where
+        Self: Unpin,
+    {
+        Pin::new(self).consume(amt)
+    }
+
+    fn read_until<'a>(&'a mut self, byte: u8, buf: &'a mut Vec<u8>) -> ReadUntilFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        ReadUntilFuture {
+            reader: self,
+            byte,
+            buf,
+            read: 0,
+        }
+    }
+
+    fn read_line<'a>(&'a mut self, buf: &'a mut String) -> ReadLineFuture<'a, Self>
+    where
+        Self: Unpin,
+    {
+        let start_len = buf.len();
+        ReadLineFuture {
+            reader: self,
+            bytes: std::mem::take(buf).into_bytes(),
+            buf,
+            start_len,
+            read: 0,
+        }
+    }
 }
 
+pub trait AsyncSeek: Seek {
+    fn seek(&mut self, pos: SeekFrom) -> SeekFuture<'_, Self>
+    where
+        Self: Unpin,
+    {
+        SeekFuture { seeker: self, pos }
+    }
+}
+
+impl<T: Read + ?Sized> AsyncRead for T {}
+impl<T: Write + ?Sized> AsyncWrite for T {}
+impl<T: BufRead + ?Sized> AsyncBufRead for T {}
+impl<T: Seek + ?Sized> AsyncSeek for T {}
+
+// =========== named futures ===========
+
 pub struct ReadFuture<'a, T: Unpin + ?Sized> {
     pub(crate) reader: &'a mut T,
     pub(crate) buf: &'a mut [u8],
@@ -48,8 +338,419 @@ impl<T: Read + Unpin + ?Sized> Future for ReadFuture<'_, T> {
     }
 }
 
-impl<T: Read + ?Sized> AsyncRead for T {}
+pub struct ReadExactFuture<'a, T: Unpin + ?Sized> {
+    reader: &'a mut T,
+    buf: &'a mut [u8],
+}
+
+impl<T: Read + Unpin + ?Sized> Future for ReadExactFuture<'_, T> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self { reader, buf } = &mut *self;
+        while !buf.is_empty() {
+            let n = ready!(Pin::new(&mut **reader).poll_read(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            *buf = &mut std::mem::take(buf)[n..];
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub struct ReadToEndFuture<'a, T: Unpin + ?Sized> {
+    reader: &'a mut T,
+    buf: &'a mut Vec<u8>,
+    start_len: usize,
+    // how many bytes of the spare capacity of `buf` are already initialized
+    initialized: usize,
+}
+
+impl<T: Read + Unpin + ?Sized> Future for ReadToEndFuture<'_, T> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self {
+            reader,
+            buf,
+            start_len,
+            initialized,
+        } = &mut *self;
+        loop {
+            if buf.len() == buf.capacity() {
+                buf.reserve(32);
+            }
+            // only zero-fill the part of the tail no earlier read has touched
+            let len = buf.len();
+            let spare = buf.spare_capacity_mut();
+            spare[*initialized..].fill(MaybeUninit::new(0));
+            *initialized = spare.len();
+            let spare = unsafe { &mut *(spare as *mut [MaybeUninit<u8>] as *mut [u8]) };
+            let n = ready!(Pin::new(&mut **reader).poll_read(cx, spare))?;
+            assert!(n <= *initialized, "read more bytes than the buffer holds");
+            if n == 0 {
+                return Poll::Ready(Ok(len - *start_len));
+            }
+            unsafe { buf.set_len(len + n) };
+            *initialized -= n;
+        }
+    }
+}
+
+pub struct ReadVectoredFuture<'a, 'b, T: Unpin + ?Sized> {
+    reader: &'a mut T,
+    bufs: &'a mut [IoSliceMut<'b>],
+}
+
+impl<T: Read + Unpin + ?Sized> Future for ReadVectoredFuture<'_, '_, T> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self { reader, bufs } = &mut *self;
+        Pin::new(&mut **reader).poll_read_vectored(cx, bufs)
+    }
+}
+
+pub struct WriteFuture<'a, T: Unpin + ?Sized> {
+    writer: &'a mut T,
+    buf: &'a [u8],
+}
+
+impl<T: Write + Unpin + ?Sized> Future for WriteFuture<'_, T> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self { writer, buf } = &mut *self;
+        Pin::new(&mut **writer).poll_write(cx, buf)
+    }
+}
+
+pub struct WriteAllFuture<'a, T: Unpin + ?Sized> {
+    writer: &'a mut T,
+    buf: &'a [u8],
+}
+
+impl<T: Write + Unpin + ?Sized> Future for WriteAllFuture<'_, T> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self { writer, buf } = &mut *self;
+        while !buf.is_empty() {
+            let n = ready!(Pin::new(&mut **writer).poll_write(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *buf = &buf[n..];
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub struct FlushFuture<'a, T: Unpin + ?Sized> {
+    writer: &'a mut T,
+}
+
+impl<T: Write + Unpin + ?Sized> Future for FlushFuture<'_, T> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.writer).poll_flush(cx)
+    }
+}
+
+pub struct CloseFuture<'a, T: Unpin + ?Sized> {
+    writer: &'a mut T,
+}
+
+impl<T: Write + Unpin + ?Sized> Future for CloseFuture<'_, T> {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.writer).poll_close(cx)
+    }
+}
+
+pub struct FillBufFuture<'a, T: Unpin + ?Sized> {
+    reader: Option<&'a mut T>,
+}
+
+impl<'a, T: BufRead + Unpin + ?Sized> Future for FillBufFuture<'a, T> {
+    type Output = io::Result<&'a [u8]>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let reader = self.reader.take().expect("polled after completion");
+        match Pin::new(&mut *reader).poll_fill_buf(cx) {
+            // SAFETY: `reader` is borrowed for `'a` and never used again, which
+            // the borrow checker can't see through the early return.
+            Poll::Ready(Ok(slice)) => Poll::Ready(Ok(unsafe { &*(slice as *const [u8]) })),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => {
+                self.reader = Some(reader);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct ReadUntilFuture<'a, T: Unpin + ?Sized> {
+    reader: &'a mut T,
+    byte: u8,
+    buf: &'a mut Vec<u8>,
+    read: usize,
+}
+
+fn poll_read_until<T: BufRead + Unpin + ?Sized>(
+    reader: &mut T,
+    cx: &mut Context<'_>,
+    byte: u8,
+    buf: &mut Vec<u8>,
+    read: &mut usize,
+) -> Poll<io::Result<usize>> {
+    loop {
+        let available = ready!(Pin::new(&mut *reader).poll_fill_buf(cx))?;
+        let (done, used) = match available.iter().position(|&b| b == byte) {
+            Some(i) => (true, i + 1),
+            None => (available.is_empty(), available.len()),
+        };
+        buf.extend_from_slice(&available[..used]);
+        Pin::new(&mut *reader).consume(used);
+        *read += used;
+        if done {
+            return Poll::Ready(Ok(std::mem::take(read)));
+        }
+    }
+}
+
+impl<T: BufRead + Unpin + ?Sized> Future for ReadUntilFuture<'_, T> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self {
+            reader,
+            byte,
+            buf,
+            read,
+        } = &mut *self;
+        poll_read_until(&mut **reader, cx, *byte, buf, read)
+    }
+}
+
+pub struct ReadLineFuture<'a, T: Unpin + ?Sized> {
+    reader: &'a mut T,
+    // The bytes of `buf`, which is left empty until the line is complete.
+    bytes: Vec<u8>,
+    buf: &'a mut String,
+    start_len: usize,
+    read: usize,
+}
+
+impl<T: BufRead + Unpin + ?Sized> Future for ReadLineFuture<'_, T> {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Self {
+            reader,
+            bytes,
+            buf,
+            start_len,
+            read,
+        } = &mut *self;
+        let res = ready!(poll_read_until(&mut **reader, cx, b'\n', bytes, read));
+        let mut bytes = std::mem::take(bytes);
+        let res = match std::str::from_utf8(&bytes[*start_len..]) {
+            Ok(_) => res,
+            Err(_) => {
+                bytes.truncate(*start_len);
+                res.and(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream did not contain valid UTF-8",
+                )))
+            }
+        };
+        // SAFETY: the bytes before `start_len` came from `buf`, and the rest
+        // is checked above.
+        **buf = unsafe { String::from_utf8_unchecked(bytes) };
+        Poll::Ready(res)
+    }
+}
+
+pub struct SeekFuture<'a, T: Unpin + ?Sized> {
+    seeker: &'a mut T,
+    pos: SeekFrom,
+}
+
+impl<T: Seek + Unpin + ?Sized> Future for SeekFuture<'_, T> {
+    type Output = io::Result<u64>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let pos = self.pos;
+        Pin::new(&mut *self.seeker).poll_seek(cx, pos)
+    }
+}
+
+// =========== dynamic dispatch ===========
 
 pub async fn call(file: &mut (dyn Read + Unpin)) -> io::Result<usize> {
     file.read(&mut []).await
 }
+
+/// `!Unpin` readers are read through `Pin<&mut dyn Read>`, which is itself
+/// an `Unpin` reader.
+pub async fn call_pinned(mut file: Pin<&mut dyn Read>) -> io::Result<usize> {
+    let mut buf = [0; 4];
+    file.read_exact(&mut buf).await?;
+    file.read(&mut buf).await
+}
+
+pub async fn call_write(file: &mut (dyn Write + Unpin)) -> io::Result<()> {
+    file.write_all(b"!\n").await?;
+    file.flush().await?;
+    file.close().await
+}
+
+pub async fn call_seek(file: &mut (dyn Seek + Unpin)) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(0)).await
+}
+
+pub async fn call_buf_read(file: &mut (dyn BufRead + Unpin)) -> io::Result<String> {
+    let mut head = [0; 2];
+    file.read_exact(&mut head).await?;
+    let mut line = String::from_utf8(head.to_vec()).unwrap();
+    file.read_line(&mut line).await?;
+
+    let (mut a, mut b) = ([0; 3], [0; 3]);
+    let n = file
+        .read_vectored(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)])
+        .await?;
+    let mut rest = a[..n].to_vec();
+    file.read_to_end(&mut rest).await?;
+    assert_eq!(rest, b"world\n!\n");
+    Ok(line)
+}
+
+mod _impl {
+    use crate::{
+        blocking::Blocking, io, BufRead, Context, PhantomPinned, Pin, Poll, Read, Seek, SeekFrom,
+        Write,
+    };
+
+    /// An in-memory file which is always ready.
+    pub struct File {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl File {
+        pub fn new(data: Vec<u8>) -> Self {
+            Self { data, pos: 0 }
+        }
+    }
+
+    impl Read for File {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let available = match self.as_mut().poll_fill_buf(cx) {
+                Poll::Ready(Ok(available)) => available,
+                poll => return poll.map_ok(|_| 0),
+            };
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            self.consume(n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl BufRead for File {
+        fn poll_fill_buf(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            Poll::Ready(Ok(&this.data[this.pos.min(this.data.len())..]))
+        }
+
+        fn consume(mut self: Pin<&mut Self>, amt: usize) {
+            self.pos += amt;
+        }
+    }
+
+    impl Write for File {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            // appends as if opened with `O_APPEND`
+            self.data.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl Seek for File {
+        fn poll_seek(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            pos: SeekFrom,
+        ) -> Poll<io::Result<u64>> {
+            let (base, offset) = match pos {
+                SeekFrom::Start(n) => (0, n as i64),
+                SeekFrom::End(n) => (self.data.len() as u64, n),
+                SeekFrom::Current(n) => (self.pos as u64, n),
+            };
+            match base.checked_add_signed(offset) {
+                Some(n) => {
+                    self.pos = n as usize;
+                    Poll::Ready(Ok(n))
+                }
+                None => Poll::Ready(Err(io::ErrorKind::InvalidInput.into())),
+            }
+        }
+    }
+
+    /// A file whose state is pinned, like the timers or the inner futures
+    /// embedded in real readers.
+    pub struct PinnedFile {
+        reads: usize,
+        _pinned: PhantomPinned,
+    }
+
+    impl PinnedFile {
+        pub fn new() -> Self {
+            Self {
+                reads: 0,
+                _pinned: PhantomPinned,
+            }
+        }
+    }
+
+    impl Read for PinnedFile {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            // SAFETY: `reads` isn't structurally pinned.
+            unsafe { self.get_unchecked_mut() }.reads += 1;
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    impl Read for Blocking {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Blocking::poll_read(self.get_mut(), cx, buf)
+        }
+    }
+}
@@ -1,11 +1,21 @@
 use stackfuture::StackFuture;
 use std::io; // pretend a Result is defined somewhere without std
 
+#[path = "common/blocking.rs"]
+mod blocking;
+
 #[pollster::main]
 async fn main() {
     let mut file = _impl::File::new();
     _ = dbg!(file.read(&mut []).await);
     _ = dbg!(call(&mut file).await);
+
+    let file: &mut dyn AsyncRead<64> = &mut blocking::open(file!()).unwrap();
+    let data = blocking::read_to_end(async |buf| file.read(buf).await).await;
+    _ = dbg!(data.map(|data| data.len()));
+    let pipe: &mut dyn AsyncRead<64> = &mut blocking::pipe(b"hello through a pipe").unwrap();
+    let data = blocking::read_to_end(async |buf| pipe.read(buf).await).await;
+    _ = dbg!(data.map(String::from_utf8));
 }
 
 pub trait AsyncRead<const N: usize> {
@@ -17,7 +27,8 @@ pub async fn call(file: &mut dyn AsyncRead<64>) -> io::Result<usize> {
 }
 
 mod _impl {
-    use crate::{io, AsyncRead, StackFuture};
+    use crate::{blocking::Blocking, io, AsyncRead, StackFuture};
+    use std::future::poll_fn;
 
     pub struct File {}
 
@@ -33,7 +44,15 @@ mod _impl {
 
     impl AsyncRead<64> for File {
         fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> StackFuture<'a, io::Result<usize>, 64> {
-            StackFuture::from_or_box(self.inner_read(buf))
+            // Fails to compile if the future doesn't fit.
+            StackFuture::from(self.inner_read(buf))
+        }
+    }
+
+    impl AsyncRead<64> for Blocking {
+        fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> StackFuture<'a, io::Result<usize>, 64> {
+            // Boxes the future if it doesn't fit.
+            StackFuture::from_or_box(poll_fn(move |cx| self.poll_read(cx, buf)))
         }
     }
 }
@@ -1,12 +1,34 @@
 use core::{
-    pin::Pin,
-    task::{Context, Poll},
+    future::Future,
+    marker::PhantomPinned,
+    mem::MaybeUninit,
+    pin::{pin, Pin},
+    task::{ready, Context, Poll},
 };
 use std::{future::poll_fn, io};
 
+#[path = "../examples/common/blocking.rs"]
+mod blocking;
+
 #[pollster::main]
 async fn main() {
     _ = dbg!(call(&mut _impl::File::new()).await);
+
+    // an `async fn` implementor handed to code expecting `dyn Read + Unpin`
+    let mut file = pin!(PollRead::<_>::new(_impl::AfitFile::new(b"hello")));
+    let mut buf = [0; 3];
+    _ = dbg!(file.read(&mut buf).await, buf);
+    _ = dbg!(file.read(&mut buf).await, buf);
+    _ = dbg!(call(&mut file).await);
+
+    _ = dbg!(call_pinned(pin!(_impl::PinnedFile::new())).await);
+
+    let file: &mut (dyn Read + Unpin) = &mut blocking::open(file!()).unwrap();
+    let data = blocking::read_to_end(async |buf| file.read(buf).await).await;
+    _ = dbg!(data.map(|data| data.len()));
+    let pipe: &mut (dyn Read + Unpin) = &mut blocking::pipe(b"hello through a pipe").unwrap();
+    let data = blocking::read_to_end(async |buf| pipe.read(buf).await).await;
+    _ = dbg!(data.map(String::from_utf8));
 }
 
 pub trait Read {
@@ -27,12 +49,149 @@ pub trait AsyncRead: Read + Unpin {
 
 impl<T: Read + Unpin + ?Sized> AsyncRead for T {}
 
+/// Readers implemented with `async fn`, which [`PollRead`] turns into [`Read`].
+#[allow(async_fn_in_trait)]
+pub trait AfitRead {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+/// Implements [`Read`] for an [`AfitRead`] by keeping the in-flight `read`
+/// future in `N` bytes of inline storage between polls.
+///
+/// The future reads into an inline buffer of `B` bytes rather than the one
+/// passed to `poll_read`, which may be another one on the next poll. Bytes
+/// that don't fit in that one are kept for the next read.
+///
+/// Pin it with [`pin!`] to get a `dyn Read + Unpin`.
+pub struct PollRead<T, const N: usize = 256, const B: usize = 1024> {
+    // borrows `reader` and `buf`, so it's dropped before them
+    in_flight: Option<InFlight>,
+    storage: Storage<N>,
+    reader: T,
+    buf: [u8; B],
+    pos: usize,
+    filled: usize,
+    _pinned: PhantomPinned,
+}
+
+#[repr(C, align(16))]
+struct Storage<const N: usize>([MaybeUninit<u8>; N]);
+
+struct InFlight {
+    poll: unsafe fn(*mut u8, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    drop: unsafe fn(*mut u8),
+}
+
+impl<T: AfitRead, const N: usize, const B: usize> PollRead<T, N, B> {
+    pub fn new(reader: T) -> Self {
+        Self {
+            in_flight: None,
+            storage: Storage([MaybeUninit::uninit(); N]),
+            reader,
+            buf: [0; B],
+            pos: 0,
+            filled: 0,
+            _pinned: PhantomPinned,
+        }
+    }
+
+    /// Starts `read` on `reader` into `buf` in `storage`.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must stay valid until the future is dropped.
+    unsafe fn start(storage: *mut u8, reader: *mut T, buf: *mut [u8]) -> InFlight {
+        unsafe fn emplace<F, const N: usize>(storage: *mut u8, fut: F) -> InFlight
+        where
+            F: Future<Output = io::Result<usize>>,
+        {
+            const {
+                assert!(
+                    size_of::<F>() <= N && align_of::<F>() <= 16,
+                    "the future of `AfitRead::read` doesn't fit in the storage of `PollRead`",
+                )
+            };
+            unsafe fn poll<F: Future>(ptr: *mut u8, cx: &mut Context<'_>) -> Poll<F::Output> {
+                Pin::new_unchecked(&mut *ptr.cast::<F>()).poll(cx)
+            }
+            unsafe fn drop<F>(ptr: *mut u8) {
+                ptr.cast::<F>().drop_in_place()
+            }
+            storage.cast::<F>().write(fut);
+            InFlight {
+                poll: poll::<F>,
+                drop: drop::<F>,
+            }
+        }
+        emplace::<_, N>(storage, (*reader).read(&mut *buf))
+    }
+}
+
+impl<T: AfitRead, const N: usize, const B: usize> Read for PollRead<T, N, B> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // SAFETY: nothing is moved out, and the in-flight future only refers
+        // to the fields of `self`, which is pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.pos == this.filled {
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let storage = this.storage.0.as_mut_ptr().cast();
+            let in_flight = this.in_flight.get_or_insert_with(|| unsafe {
+                let len = buf.len().min(B);
+                Self::start(storage, &mut this.reader, &mut this.buf[..len])
+            });
+            let res = ready!(unsafe { (in_flight.poll)(storage, cx) });
+            unsafe { (in_flight.drop)(storage) };
+            this.in_flight = None;
+            (this.pos, this.filled) = (0, res?);
+        }
+        let n = (this.filled - this.pos).min(buf.len());
+        buf[..n].copy_from_slice(&this.buf[this.pos..][..n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A pinned reader is an `Unpin` reader, e.g. a pinned [`PollRead`] or
+/// `Pin<&mut dyn Read>`.
+impl<P> Read for Pin<P>
+where
+    P: core::ops::DerefMut<Target: Read> + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().as_mut().poll_read(cx, buf)
+    }
+}
+
+impl<T, const N: usize, const B: usize> Drop for PollRead<T, N, B> {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.in_flight.take() {
+            unsafe { (in_flight.drop)(self.storage.0.as_mut_ptr().cast()) }
+        }
+    }
+}
+
 pub async fn call(file: &mut (dyn Read + Unpin)) -> io::Result<usize> {
     file.read(&mut []).await
 }
 
+/// `!Unpin` readers are read through `Pin<&mut dyn Read>`, which is itself
+/// an `Unpin` reader.
+pub async fn call_pinned(mut file: Pin<&mut dyn Read>) -> io::Result<usize> {
+    file.read(&mut [0; 4]).await
+}
+
 mod _impl {
-    use crate::{io, Context, Pin, Poll, Read};
+    use crate::{blocking::Blocking, io, AfitRead, Context, PhantomPinned, Pin, Poll, Read};
 
     pub struct File {}
 
@@ -51,4 +210,72 @@ mod _impl {
             Poll::Ready(Ok(buf.len()))
         }
     }
+
+    /// A file implemented with `async fn`, whose reads are pending once.
+    pub struct AfitFile {
+        data: &'static [u8],
+    }
+
+    impl AfitFile {
+        pub fn new(data: &'static [u8]) -> Self {
+            Self { data }
+        }
+    }
+
+    impl AfitRead for AfitFile {
+        async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+            let n = self.data.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    /// A file whose state is pinned, like the timers or the inner futures
+    /// embedded in real readers.
+    pub struct PinnedFile {
+        reads: usize,
+        _pinned: PhantomPinned,
+    }
+
+    impl PinnedFile {
+        pub fn new() -> Self {
+            Self {
+                reads: 0,
+                _pinned: PhantomPinned,
+            }
+        }
+    }
+
+    impl Read for PinnedFile {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            // SAFETY: `reads` isn't structurally pinned.
+            unsafe { self.get_unchecked_mut() }.reads += 1;
+            Poll::Ready(Ok(buf.len()))
+        }
+    }
+
+    impl Read for Blocking {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Blocking::poll_read(self.get_mut(), cx, buf)
+        }
+    }
 }